@@ -1,8 +1,26 @@
 use avail_rust::prelude::*;
+use chrono::{DateTime, Utc};
 use dotenvy::dotenv;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use indicatif::{ProgressBar, ProgressStyle};
+use lru::LruCache;
+use std::collections::HashMap;
 use std::env;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use reqwest::Client;
+use std::fmt;
+use std::future::Future;
+use std::io::{Read, Write};
+use std::num::NonZeroUsize;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
 
 use avail::data_availability::storage::types::app_keys::Param0;
 
@@ -12,105 +30,910 @@ type DataSubmissionCall = avail::data_availability::calls::types::SubmitData;
 const AVAIL_LIGHT_NODE_HTTP_URL: &str = "https://api.lightclient.turing.avail.so";
 const AVAIL_LIGHT_NODE_WS_URL: &str = "wss://turing-rpc.avail.so/ws";
 
-/// Load the AVAIL_SEED_PHRASE from .env and return an account
-fn load_account_from_env() -> Result<Keypair, ClientError> {
+const AVAIL_MAINNET_LIGHT_NODE_HTTP_URL: &str = "https://api.lightclient.mainnet.avail.so";
+const AVAIL_MAINNET_LIGHT_NODE_WS_URL: &str = "wss://mainnet-rpc.avail.so/ws";
+
+/// Planck per AVAIL: balances are fixed-point with 18 decimals
+pub const PLANCK_PER_AVAIL: u128 = 1_000_000_000_000_000_000;
+
+/// Default balance, in Planck, below which the account is considered unable
+/// to pay for a submission: 0.1 AVAIL, comfortably above a typical data
+/// submission fee
+pub const DEFAULT_MIN_BALANCE: u128 = PLANCK_PER_AVAIL / 10;
+
+/// Default number of blocks fetched concurrently during a scan
+pub const DEFAULT_FETCH_CONCURRENCY: usize = 8;
+
+/// Fetch concurrency above which public nodes are likely to throttle or ban
+/// the client; higher settings are allowed but warned about
+pub const HIGH_FETCH_CONCURRENCY: usize = 32;
+
+/// Default blob size in bytes above which submissions are gzip-compressed
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 512;
+
+/// Largest `submit_data` payload the Avail runtime accepts, in bytes
+const AVAIL_MAX_BLOB_SIZE: usize = 512 * 1024;
+
+/// Default time a transaction may take from submission to inclusion
+pub const DEFAULT_INCLUSION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Time limit of every request to the light client HTTP API. Almost every
+/// read and write asks the light client for the latest height first, so a
+/// hung endpoint must fail fast instead of stalling them.
+pub const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default number of finalized blocks whose blobs are kept in memory
+pub const DEFAULT_BLOCK_CACHE_SIZE: usize = 256;
+
+/// Default limit on requests per second to the node and light client:
+/// enough for a scan at the default concurrency, low enough to stay clear of
+/// the rate limits of public endpoints
+pub const DEFAULT_REQUESTS_PER_SECOND: u32 = 20;
+
+/// Storage entries the node returns per request when iterating a storage
+/// map, the page size of the client's RPC backend. Iterations take one
+/// rate limit permit per page.
+const STORAGE_PAGE_SIZE: usize = 32;
+
+/// Fewest blocks a fetch must cover to show a progress bar, so quick reads
+/// don't flash one
+const PROGRESS_MIN_BLOCKS: u32 = 50;
+
+/// First byte of a gzip-compressed blob. JSON blobs never start with a NUL byte.
+const COMPRESSED_BLOB_HEADER: u8 = 0x00;
+
+/// The Avail network a session is connected to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Network {
+    #[default]
+    Turing,
+    Mainnet,
+}
+
+impl Network {
+    /// Largest blob in bytes a single data submission may carry on this network
+    pub fn max_blob_size(&self) -> usize {
+        match self {
+            Network::Turing | Network::Mainnet => AVAIL_MAX_BLOB_SIZE,
+        }
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Network::Turing => write!(f, "turing"),
+            Network::Mainnet => write!(f, "mainnet"),
+        }
+    }
+}
+
+impl FromStr for Network {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "turing" => Ok(Network::Turing),
+            "mainnet" => Ok(Network::Mainnet),
+            _ => Err(format!("Unknown network: '{}' (expected 'turing' or 'mainnet')", s)),
+        }
+    }
+}
+
+/// HTTP (light client API) and WS (node RPC) endpoints to connect to
+#[derive(Debug, Clone)]
+pub struct AvailEndpoints {
+    pub http_url: String,
+    pub ws_url: String,
+    /// Tried in order when `http_url` fails
+    pub http_fallback_urls: Vec<String>,
+    /// Tried in order when connecting to `ws_url` fails
+    pub ws_fallback_urls: Vec<String>,
+}
+
+impl Default for AvailEndpoints {
+    fn default() -> Self {
+        Self::for_network(Network::default())
+    }
+}
+
+impl AvailEndpoints {
+    /// The public endpoints for the given network
+    pub fn for_network(network: Network) -> Self {
+        let (http_url, ws_url) = match network {
+            Network::Turing => (AVAIL_LIGHT_NODE_HTTP_URL, AVAIL_LIGHT_NODE_WS_URL),
+            Network::Mainnet => (AVAIL_MAINNET_LIGHT_NODE_HTTP_URL, AVAIL_MAINNET_LIGHT_NODE_WS_URL),
+        };
+
+        Self {
+            http_url: http_url.to_string(),
+            ws_url: ws_url.to_string(),
+            http_fallback_urls: Vec::new(),
+            ws_fallback_urls: Vec::new(),
+        }
+    }
+
+    /// Read `AVAIL_HTTP_URL` and `AVAIL_WS_URL` from the environment (or .env),
+    /// falling back to the network's public endpoints when unset. Each may
+    /// hold a comma-separated list: the first URL is used, the rest are
+    /// fallbacks in order.
+    pub fn from_env(network: Network) -> Result<Self, ClientError> {
+        dotenv().ok();
+        let defaults = Self::for_network(network);
+
+        let (http_url, http_fallback_urls) = url_list_from_env("AVAIL_HTTP_URL", defaults.http_url);
+        let (ws_url, ws_fallback_urls) = url_list_from_env("AVAIL_WS_URL", defaults.ws_url);
+        let endpoints = Self { http_url, ws_url, http_fallback_urls, ws_fallback_urls };
+        endpoints.validate()?;
+
+        Ok(endpoints)
+    }
+
+    /// Every HTTP URL, primary first
+    pub fn http_urls(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.http_url.as_str()).chain(self.http_fallback_urls.iter().map(String::as_str))
+    }
+
+    /// Every WS URL, primary first
+    pub fn ws_urls(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.ws_url.as_str()).chain(self.ws_fallback_urls.iter().map(String::as_str))
+    }
+
+    /// Check that each URL uses the scheme expected for its transport
+    pub fn validate(&self) -> Result<(), ClientError> {
+        for url in self.http_urls() {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(format!(
+                    "Invalid HTTP URL '{}': must start with http:// or https://",
+                    url
+                ).into());
+            }
+        }
+        for url in self.ws_urls() {
+            if !url.starts_with("ws://") && !url.starts_with("wss://") {
+                return Err(format!(
+                    "Invalid WS URL '{}': must start with ws:// or wss://",
+                    url
+                ).into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Splits a comma-separated URL list from the environment into the first
+/// URL and the rest, using `default` alone when the variable is unset or empty
+fn url_list_from_env(name: &str, default: String) -> (String, Vec<String>) {
+    let urls: Vec<String> = env::var(name)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .map(str::to_string)
+        .collect();
+
+    match urls.split_first() {
+        Some((first, rest)) => (first.clone(), rest.to_vec()),
+        None => (default, Vec::new()),
+    }
+}
+
+/// How far reads (and app ID lookups) may look
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlockFinality {
+    /// Up to the newest block, including blocks a reorg could still drop:
+    /// fresher, but a record may appear and then vanish
+    Best,
+    /// Up to the latest finalized block only, so nothing read can be undone
+    #[default]
+    Finalized,
+}
+
+/// Retry policy for transient RPC failures
+#[derive(Debug, Clone)]
+pub struct RetryConfig {
+    /// Total number of attempts, including the first one
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubled after every failed attempt
+    pub base_delay: Duration,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Spaces requests evenly so a connection sends at most
+/// `requests_per_second` of them; a limit of 0 disables it. Requests that
+/// arrive faster wait for their turn, in arrival order.
+pub struct RateLimiter {
+    requests_per_second: u32,
+    /// When the next request may be sent
+    next: Mutex<Instant>,
+    /// Whether the last request had to wait, so throttling is logged when it
+    /// starts rather than for every delayed request
+    throttling: AtomicBool,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_second: u32) -> Self {
+        Self {
+            requests_per_second,
+            next: Mutex::new(Instant::now()),
+            throttling: AtomicBool::new(false),
+        }
+    }
+
+    /// Waits until the next request may be sent
+    pub async fn acquire(&self) {
+        if self.requests_per_second == 0 {
+            return;
+        }
+        let interval = Duration::from_secs(1) / self.requests_per_second;
+
+        let send_at = {
+            let mut next = self.next.lock().unwrap();
+            let send_at = (*next).max(Instant::now());
+            *next = send_at + interval;
+            send_at
+        };
+
+        let delay = send_at.saturating_duration_since(Instant::now());
+        if delay.is_zero() {
+            self.throttling.store(false, Ordering::Relaxed);
+            return;
+        }
+        if !self.throttling.swap(true, Ordering::Relaxed) {
+            info!("Throttling requests to {} per second", self.requests_per_second);
+        }
+        tokio::time::sleep_until(send_at).await;
+    }
+}
+
+/// A persistent connection to an Avail node, shared across all operations
+/// so that a block scan reuses one WebSocket instead of reconnecting per block
+pub struct AvailConnection {
+    pub sdk: SDK,
+    /// Account signing every transaction, see `AvailConnection::account`
+    signer: Signer,
+    /// HTTP client for the light client API, reused so requests share its connection pool
+    pub http: Client,
+    pub network: Network,
+    pub endpoints: AvailEndpoints,
+    pub retry: RetryConfig,
+    /// Throttles every RPC and light client request, retries included
+    pub rate_limiter: RateLimiter,
+    /// Maximum number of blocks fetched concurrently during a scan
+    pub concurrency: usize,
+    /// Which blocks reads may see
+    pub read_finality: BlockFinality,
+    /// Blobs larger than this many bytes are gzip-compressed when that makes
+    /// them smaller; `None` disables compression
+    pub compression_threshold: Option<usize>,
+    /// How long each attempt at a transaction may take to be included
+    /// before it gives up
+    pub inclusion_timeout: Duration,
+    /// Log submissions instead of sending them, see `submit_data_to_avail_by_app_id`
+    pub dry_run: bool,
+    /// Draw a progress bar on stderr while fetching long block ranges. Off by
+    /// default; the CLI turns it on when stderr is a terminal.
+    pub show_progress: bool,
+    /// Hashes of finalized block heights, which can no longer change
+    block_hashes: Mutex<HashMap<u32, H256>>,
+    /// Decoded blobs of finalized blocks by `(block_hash, app_id)`; `None` when disabled
+    block_data: Mutex<Option<LruCache<(H256, u32), Vec<BlockBlob>>>>,
+    /// App IDs resolved so far in this session by app name. App keys can't
+    /// be removed or reassigned, so entries never go stale.
+    app_ids: Mutex<HashMap<String, u32>>,
+    /// Index into `endpoints.http_urls()` of the endpoint that last answered,
+    /// tried first so dead endpoints are not retried on every call
+    healthy_http: AtomicUsize,
+}
+
+impl AvailConnection {
+    /// Opens the WebSocket connection to the Avail node of the given network,
+    /// trying each WS URL in order until one connects. Signs with the named
+    /// account when `account` is given, see `seed_phrase_env`. With `signing`
+    /// set, the account is loaded first so a missing or invalid seed phrase
+    /// fails up front rather than on the first write; without it, as for a
+    /// read-only or dry-run session, no seed phrase is needed until something
+    /// asks for the account.
+    pub async fn connect(
+        network: Network,
+        endpoints: AvailEndpoints,
+        account: Option<&str>,
+        signing: bool,
+    ) -> Result<Self, ClientError> {
+        let signer = Signer::new(account, signing)?;
+
+        let mut connected = None;
+        let mut last_error = None;
+        for url in endpoints.ws_urls() {
+            match SDK::new(url).await {
+                Ok(sdk) => {
+                    if url != endpoints.ws_url {
+                        info!("Connected to fallback WS endpoint {}", url);
+                    }
+                    connected = Some(sdk);
+                    break;
+                }
+                Err(e) => {
+                    warn!("Failed to connect to {}: {}", url, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        let Some(sdk) = connected else {
+            return Err(last_error.expect("there is always at least one WS URL"));
+        };
+
+        let http = Client::builder()
+            .timeout(HTTP_TIMEOUT)
+            .build()
+            .map_err(|e| ClientError::from(format!("Failed to create HTTP client: {}", e)))?;
+
+        Ok(Self {
+            sdk,
+            signer,
+            http,
+            network,
+            endpoints,
+            retry: RetryConfig::default(),
+            rate_limiter: RateLimiter::new(DEFAULT_REQUESTS_PER_SECOND),
+            concurrency: DEFAULT_FETCH_CONCURRENCY,
+            read_finality: BlockFinality::default(),
+            compression_threshold: Some(DEFAULT_COMPRESSION_THRESHOLD),
+            inclusion_timeout: DEFAULT_INCLUSION_TIMEOUT,
+            dry_run: false,
+            show_progress: false,
+            block_hashes: Mutex::new(HashMap::new()),
+            block_data: Mutex::new(NonZeroUsize::new(DEFAULT_BLOCK_CACHE_SIZE).map(LruCache::new)),
+            app_ids: Mutex::new(HashMap::new()),
+            healthy_http: AtomicUsize::new(0),
+        })
+    }
+
+    /// The account signing every transaction, loaded on first use from
+    /// `AVAIL_SEED_PHRASE` or the named account's `AVAIL_SEED_<NAME>`. Fails
+    /// if that seed phrase is missing or invalid.
+    pub fn account(&self) -> Result<&Keypair, ClientError> {
+        self.signer.keypair()
+    }
+
+    /// Resizes the block data cache to hold `size` blocks, dropping its
+    /// current entries. A size of 0 disables the cache.
+    pub fn set_block_cache_size(&self, size: usize) {
+        *self.block_data.lock().unwrap() = NonZeroUsize::new(size).map(LruCache::new);
+    }
+
+    /// Forgets every cached block's blobs
+    pub fn clear_block_data_cache(&self) {
+        if let Some(cache) = self.block_data.lock().unwrap().as_mut() {
+            cache.clear();
+        }
+    }
+
+    /// Forgets every cached block hash
+    pub fn clear_block_hash_cache(&self) {
+        self.block_hashes.lock().unwrap().clear();
+    }
+}
+
+/// Why a transaction (`submit_data` or `create_application_key`) failed.
+/// Backends still surface it as `DatabaseError::AvailError`, with this
+/// type's message as context.
+#[derive(Debug, Error)]
+pub enum AvailOpError {
+    /// The node could not be reached, or the connection dropped; worth retrying
+    #[error("Connection failed: {0}")]
+    Connection(String),
+
+    /// The transaction was included but its execution failed, e.g. for lack
+    /// of funds; retrying the same transaction fails the same way
+    #[error("Transaction {tx_hash:?} failed in block {block_hash:?}")]
+    TransactionFailed { tx_hash: H256, block_hash: H256 },
+
+    /// No block included the transaction within `AvailConnection::inclusion_timeout`.
+    /// It may still be included later, so it is not retried.
+    #[error("Transaction was not included within {0:?}")]
+    Timeout(Duration),
+
+    /// The transaction went through, but what came back could not be decoded
+    #[error("Failed to decode the included transaction: {0}")]
+    Decode(String),
+
+    /// Any other error from the Avail client
+    #[error("{0}")]
+    Client(String),
+}
+
+impl AvailOpError {
+    /// Whether trying the operation again may succeed
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, AvailOpError::Connection(_))
+    }
+}
+
+/// Classifies an error from the Avail client, which only exposes its
+/// message: network and timeout-class failures are `Connection`, anything
+/// else `Client`
+impl From<ClientError> for AvailOpError {
+    fn from(e: ClientError) -> Self {
+        let message = e.to_string();
+        if is_transient_error(&message) {
+            AvailOpError::Connection(message)
+        } else {
+            AvailOpError::Client(message)
+        }
+    }
+}
+
+impl From<avail_rust::subxt::Error> for AvailOpError {
+    fn from(e: avail_rust::subxt::Error) -> Self {
+        Self::from(ClientError::from(e))
+    }
+}
+
+/// Lets the read functions, which return `ClientError`, propagate a failure
+/// from `with_retry` with `?`
+impl From<AvailOpError> for ClientError {
+    fn from(e: AvailOpError) -> Self {
+        ClientError::from(e.to_string())
+    }
+}
+
+/// Returns true for network/timeout-class errors that are worth retrying
+fn is_transient_error(message: &str) -> bool {
+    let message = message.to_lowercase();
+    [
+        "timeout",
+        "timed out",
+        "connection",
+        "disconnected",
+        "websocket",
+        "transport",
+        "restart needed",
+        "i/o error",
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
+}
+
+/// Runs `operation` under `conn`'s rate limit, retrying failures whose
+/// `AvailOpError` is retryable with exponential backoff and following
+/// `conn.retry`. Other failures are returned immediately; once the attempts
+/// are exhausted the last error is returned.
+async fn with_retry<T, E, F, Fut>(conn: &AvailConnection, mut operation: F) -> Result<T, AvailOpError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: Into<AvailOpError>,
+{
+    let retry = &conn.retry;
+    let mut attempt = 1;
+    loop {
+        conn.rate_limiter.acquire().await;
+        match operation().await.map_err(Into::into) {
+            Ok(value) => return Ok(value),
+            Err(e) => {
+                if attempt >= retry.max_attempts || !e.is_retryable() {
+                    return Err(e);
+                }
+                let delay = retry.base_delay.saturating_mul(1 << (attempt - 1).min(16));
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Runs `execute`, which signs a transaction with the connection's account
+/// under the given nonce, broadcasts it and waits for its inclusion. Each
+/// attempt gets `conn.inclusion_timeout`.
+///
+/// The account's next nonce is looked up once and every attempt signs with
+/// it. A transaction can reach the node even though its attempt failed, e.g.
+/// when the connection drops while waiting for inclusion; a resend then
+/// reuses that transaction's nonce, so the chain rejects it instead of
+/// including the write twice.
+async fn broadcast_and_watch_inclusion<T, F, Fut>(conn: &AvailConnection, execute: F) -> Result<T, AvailOpError>
+where
+    F: Fn(Nonce) -> Fut,
+    Fut: Future<Output = Result<T, ClientError>>,
+{
+    let address = conn.account()?.public_key().to_account_id().to_string();
+    let nonce = with_retry(conn, || rpc::system::account_next_index(&conn.sdk.client, address.clone())).await?;
+
+    with_retry(conn, || async {
+        tokio::time::timeout(conn.inclusion_timeout, execute(Nonce::Custom(nonce)))
+            .await
+            .map_err(|_| AvailOpError::Timeout(conn.inclusion_timeout))?
+            .map_err(AvailOpError::from)
+    }).await
+}
+
+/// Gzip-compresses `data` behind a header byte when it exceeds `threshold`
+/// and compression actually reduces its size; otherwise returns it unchanged
+fn encode_blob(data: Vec<u8>, threshold: Option<usize>) -> Result<Vec<u8>, ClientError> {
+    let Some(threshold) = threshold else {
+        return Ok(data);
+    };
+    if data.len() <= threshold {
+        return Ok(data);
+    }
+
+    let mut encoder = GzEncoder::new(vec![COMPRESSED_BLOB_HEADER], Compression::default());
+    encoder.write_all(&data)
+        .map_err(|e| ClientError::from(format!("Failed to compress blob: {}", e)))?;
+    let compressed = encoder.finish()
+        .map_err(|e| ClientError::from(format!("Failed to compress blob: {}", e)))?;
+
+    if compressed.len() < data.len() {
+        Ok(compressed)
+    } else {
+        Ok(data)
+    }
+}
+
+/// Decodes a blob as stored on-chain, decompressing it if it carries the
+/// compression header. Uncompressed blobs are read as-is. Fails for blobs
+/// that are not UTF-8 text, as other applications sharing the app ID may
+/// submit.
+fn decode_blob(bytes: Vec<u8>) -> Result<String, ClientError> {
+    if bytes.first() != Some(&COMPRESSED_BLOB_HEADER) {
+        return String::from_utf8(bytes)
+            .map_err(|e| ClientError::from(format!("Blob is not valid UTF-8 text: {}", e)));
+    }
+
+    let mut decompressed = String::new();
+    GzDecoder::new(&bytes[1..])
+        .read_to_string(&mut decompressed)
+        .map_err(|e| ClientError::from(format!("Failed to decompress blob: {}", e)))?;
+
+    Ok(decompressed)
+}
+
+/// Environment variable holding the seed phrase of the default account
+pub const SEED_PHRASE_ENV: &str = "AVAIL_SEED_PHRASE";
+
+/// Environment variable holding the seed phrase of an account:
+/// `AVAIL_SEED_PHRASE` for the default account, `AVAIL_SEED_<NAME>` for a
+/// named one, with the name uppercased and dashes turned into underscores,
+/// e.g. `AVAIL_SEED_TEST_2` for `test-2`
+pub fn seed_phrase_env(account: Option<&str>) -> String {
+    match account {
+        Some(name) => format!("AVAIL_SEED_{}", name.to_uppercase().replace('-', "_")),
+        None => SEED_PHRASE_ENV.to_string(),
+    }
+}
+
+/// A signing account, loaded from the environment at most once
+struct Signer {
+    name: Option<String>,
+    keypair: OnceLock<Keypair>,
+}
+
+impl Signer {
+    /// Loads the account right away when `required`, otherwise on the first
+    /// `keypair` call
+    fn new(name: Option<&str>, required: bool) -> Result<Self, ClientError> {
+        let signer = Self { name: name.map(str::to_string), keypair: OnceLock::new() };
+        if required {
+            signer.keypair()?;
+        }
+
+        Ok(signer)
+    }
+
+    fn keypair(&self) -> Result<&Keypair, ClientError> {
+        if let Some(keypair) = self.keypair.get() {
+            return Ok(keypair);
+        }
+        let keypair = load_account_from_env(self.name.as_deref())?;
+
+        Ok(self.keypair.get_or_init(|| keypair))
+    }
+}
+
+/// Load the seed phrase of the given account, or of the default account,
+/// from .env and return an account
+fn load_account_from_env(account: Option<&str>) -> Result<Keypair, ClientError> {
     dotenv().ok();
-    let seed = env::var("AVAIL_SEED_PHRASE")
-        .map_err(|_| "Missing AVAIL_SEED_PHRASE environment variable")?;
+    if let Some(name) = account {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(format!("Invalid account name '{}': use letters, digits, '_' and '-'", name).into());
+        }
+    }
+
+    let var = seed_phrase_env(account);
+    let seed = env::var(&var).map_err(|_| match account {
+        Some(name) => format!("Missing {} environment variable for account '{}'", var, name),
+        None => format!("Missing {} environment variable", var),
+    })?;
+    if let Some(name) = account {
+        info!("Signing with account '{}' from {}", name, var);
+    }
 
     account::from_secret_uri(&seed)
 }
 
-/// Checks if an app ID (application key) exists on-chain by name
-pub async fn does_app_id_exist_on_avail(app_name: &str) -> Result<Option<u32>, ClientError> {
-    let sdk = SDK::new(AVAIL_LIGHT_NODE_WS_URL).await?;
+/// Checks if an app ID (application key) exists on-chain by name. Reads at
+/// the block `conn.read_finality` allows (finalized by default), retries
+/// transient failures, and serves names already resolved on this
+/// connection from memory.
+pub async fn does_app_id_exist_on_avail(
+    conn: &AvailConnection,
+    app_name: &str
+) -> Result<Option<u32>, ClientError> {
+    if let Some(app_id) = conn.app_ids.lock().unwrap().get(app_name).copied() {
+        return Ok(Some(app_id));
+    }
+
+    let sdk = &conn.sdk;
 
     let key = Param0 { 0: app_name.as_bytes().to_vec() };
 
-    let block_hash = sdk.client.best_block_hash().await?;
+    let block_hash = match conn.read_finality {
+        BlockFinality::Best => with_retry(conn, || sdk.client.best_block_hash()).await?,
+        BlockFinality::Finalized => get_finalized_block_hash_on_avail(conn).await?,
+    };
     let storage = sdk.client.storage().at(block_hash);
 
     let storage_key = avail::storage().data_availability().app_keys(key);
-    let result = storage.fetch(&storage_key).await?;
+    let result = with_retry(conn, || storage.fetch(&storage_key)).await?;
 
     if let Some(app_key_info) = result {
         // app_key_info.id.0 is the app_id (u32)
         let app_id = app_key_info.id.0;
+        conn.app_ids.lock().unwrap().insert(app_name.to_string(), app_id);
         Ok(Some(app_id))
     } else {
         Ok(None)
     }
 }
 
-/// Creates a new app ID on AvailDA
-pub async fn create_app_id_on_avail(app_name: &str) -> Result<(), ClientError> {
-    dotenv().ok();
+/// An application key and the app ID it was registered under
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AppKey {
+    pub name: String,
+    pub id: u32,
+}
+
+/// Lists the application keys owned by the signing account, sorted by ID.
+/// Iterates over every registered app key, so it takes a while on networks
+/// with many apps.
+pub async fn list_owned_app_ids_on_avail(
+    conn: &AvailConnection
+) -> Result<Vec<AppKey>, ClientError> {
+    let sdk = &conn.sdk;
+    let owner = conn.account()?.public_key().to_account_id();
+
+    let block_hash = match conn.read_finality {
+        BlockFinality::Best => with_retry(conn, || sdk.client.best_block_hash()).await?,
+        BlockFinality::Finalized => get_finalized_block_hash_on_avail(conn).await?,
+    };
+    let storage = sdk.client.storage().at(block_hash);
+
+    let mut entries = with_retry(conn, || {
+        storage.iter(avail::storage().data_availability().app_keys_iter())
+    })
+    .await?;
 
-    let account = load_account_from_env()?;
+    let mut owned = Vec::new();
+    let mut seen: usize = 0;
+    loop {
+        // Pages are fetched as the iteration reaches them, each by the
+        // `next` call that runs past the previous page
+        if seen.is_multiple_of(STORAGE_PAGE_SIZE) {
+            conn.rate_limiter.acquire().await;
+        }
+        let Some(entry) = entries.next().await else {
+            break;
+        };
+        seen += 1;
+        let entry = entry?;
+        if entry.value.owner != owner {
+            continue;
+        }
+        let Some(name) = app_name_from_storage_key(&entry.key_bytes) else {
+            continue;
+        };
+        owned.push(AppKey { name, id: entry.value.id.0 });
+    }
+    owned.sort_by_key(|app| app.id);
 
-    let sdk = SDK::new(AVAIL_LIGHT_NODE_WS_URL).await?;
+    Ok(owned)
+}
+
+/// Recovers the app name from a full `AppKeys` storage key: 32 bytes of
+/// pallet and storage prefix, the name's 16-byte blake2_128 hash, then the
+/// name itself SCALE-encoded as a compact length followed by its bytes
+fn app_name_from_storage_key(key: &[u8]) -> Option<String> {
+    let encoded = key.get(48..)?;
+
+    let (len, offset) = match encoded.first()? & 0b11 {
+        0b00 => ((encoded[0] >> 2) as usize, 1),
+        0b01 => ((u16::from_le_bytes(encoded.get(..2)?.try_into().ok()?) >> 2) as usize, 2),
+        0b10 => ((u32::from_le_bytes(encoded.get(..4)?.try_into().ok()?) >> 2) as usize, 4),
+        _ => return None,
+    };
+    let name = encoded.get(offset..offset + len)?;
+
+    String::from_utf8(name.to_vec()).ok()
+}
+
+/// Creates a new app ID on AvailDA and returns it
+pub async fn create_app_id_on_avail(
+    conn: &AvailConnection,
+    app_name: &str
+) -> Result<u32, AvailOpError> {
+    let sdk = &conn.sdk;
+    let account = conn.account()?;
 
     let app_name_bytes = app_name.as_bytes().to_vec();
 
     let tx = sdk.tx.data_availability.create_application_key(app_name_bytes);
-    let result = tx.execute_and_watch_inclusion(&account, Options::default()).await?;
+    let result = broadcast_and_watch_inclusion(conn, |nonce| {
+        tx.execute_and_watch_inclusion(account, Options::new().nonce(nonce))
+    }).await?;
     if result.is_successful() != Some(true) {
-        return Err("Transaction failed".into());
+        return Err(AvailOpError::TransactionFailed { tx_hash: result.tx_hash, block_hash: result.block_hash });
     }
 
-    let events = result.events.as_ref().unwrap();
-    let event = events.find_first::<ApplicationKeyCreatedEvent>().unwrap();
-    let Some(_event) = event else {
-        return Err("Failed to find ApplicationKeyCreated event".into());
+    // The transaction succeeded, so the app ID exists on-chain: these
+    // errors only mean it couldn't be read from this result, and a lookup
+    // by name on the next start will find it
+    let Some(events) = result.events.as_ref() else {
+        return Err(AvailOpError::Decode(format!(
+            "app '{}' was created in block {:?}, but its events could not be fetched",
+            app_name, result.block_hash
+        )));
     };
+    let event = events.find_first::<ApplicationKeyCreatedEvent>().map_err(|e| {
+        AvailOpError::Decode(format!("failed to decode the ApplicationKeyCreated event: {}", e))
+    })?;
+    let Some(event) = event else {
+        return Err(AvailOpError::Decode(format!(
+            "app '{}' was created in block {:?}, but it has no ApplicationKeyCreated event",
+            app_name, result.block_hash
+        )));
+    };
+
+    conn.app_ids.lock().unwrap().insert(app_name.to_string(), event.id.0);
 
-    Ok(())
+    Ok(event.id.0)
 }
 
-/// Submit a data to the AvailDA light node
+/// Where a data submission landed on-chain
+#[derive(Debug, Clone)]
+pub struct SubmissionReceipt {
+    pub tx_hash: H256,
+    pub block_hash: H256,
+    pub block_height: u32,
+    /// The data as decoded back from the included transaction
+    pub data: String,
+}
+
+/// Submit a data to the AvailDA light node. With `conn.dry_run` set, the
+/// blob is only logged and a receipt with zero hashes is returned.
 pub async fn submit_data_to_avail_by_app_id(
+    conn: &AvailConnection,
     app_id: u32,
     data: String,
-) -> Result<String, ClientError> {
-    dotenv().ok();
+) -> Result<SubmissionReceipt, AvailOpError> {
+    if conn.dry_run {
+        let encoded = encode_blob(data.clone().into_bytes(), conn.compression_threshold)?;
+        info!(
+            "[dry run] Not submitting {} bytes ({} encoded) under app ID {}: {}",
+            data.len(), encoded.len(), app_id, data
+        );
 
-    let account = load_account_from_env()?;
+        // Nothing is included anywhere: zero hashes at the current read height
+        let block_height = get_read_height_on_avail(conn).await?;
+        return Ok(SubmissionReceipt {
+            tx_hash: H256::zero(),
+            block_hash: H256::zero(),
+            block_height,
+            data,
+        });
+    }
 
-    let blob = String::from(data).into_bytes();
+    let blob = encode_blob(data.into_bytes(), conn.compression_threshold)?;
 
-    let sdk = SDK::new(AVAIL_LIGHT_NODE_WS_URL).await?;
-    let options = Options::new().app_id(app_id);
+    let sdk = &conn.sdk;
+    let account = conn.account()?;
 
     let tx = sdk.tx.data_availability.submit_data(blob);
-    let result = tx.execute_and_watch_inclusion(&account, options).await?;
+    let result = broadcast_and_watch_inclusion(conn, |nonce| {
+        tx.execute_and_watch_inclusion(account, Options::new().app_id(app_id).nonce(nonce))
+    }).await?;
     if result.is_successful() != Some(true) {
-        return Err("Transaction failed".into());
+        return Err(AvailOpError::TransactionFailed { tx_hash: result.tx_hash, block_hash: result.block_hash });
     }
 
-    let decoded = result.decode_as::<DataSubmissionCall>().await?;
+    let decoded = result
+        .decode_as::<DataSubmissionCall>()
+        .await
+        .map_err(|e| AvailOpError::Decode(e.to_string()))?;
     let Some(decoded) = decoded else {
-        return Err("Failed to decode data submission call".into());
+        return Err(AvailOpError::Decode("no data submission call".to_string()));
     };
 
-    let data_decoded = to_ascii(decoded.data.0).unwrap();
+    let data_decoded = decode_blob(decoded.data.0).map_err(|e| AvailOpError::Decode(e.to_string()))?;
+
+    Ok(SubmissionReceipt {
+        tx_hash: result.tx_hash,
+        block_hash: result.block_hash,
+        block_height: result.block_number,
+        data: data_decoded,
+    })
+}
+
+/// Fetch the latest block height from the Avail light client HTTP API.
+/// Starts at the endpoint that last answered and fails over to the next
+/// one in order; the error of the last endpoint tried is returned if none
+/// answers.
+pub async fn get_latest_block_height_on_avail(
+    conn: &AvailConnection
+) -> Result<u32, Box<dyn std::error::Error>> {
+    let urls: Vec<&str> = conn.endpoints.http_urls().collect();
+    let healthy = conn.healthy_http.load(Ordering::Relaxed);
 
-    Ok(data_decoded)
+    let mut last_error = None;
+    for offset in 0..urls.len() {
+        let index = (healthy + offset) % urls.len();
+        match get_latest_block_height_from(conn, urls[index]).await {
+            Ok(height) => {
+                if index != healthy {
+                    info!("Switched to HTTP endpoint {}", urls[index]);
+                    conn.healthy_http.store(index, Ordering::Relaxed);
+                }
+                return Ok(height);
+            }
+            Err(e) => {
+                if urls.len() > 1 {
+                    warn!("HTTP endpoint {} failed: {}", urls[index], e);
+                }
+                // Kept as a string: the boxed error is not `Send`
+                last_error = Some(e.to_string());
+            }
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| "No HTTP endpoint configured".to_string()).into())
 }
 
-/// Fetch the latest block height from the Avail light client HTTP API
-pub async fn get_latest_block_height_on_avail() -> Result<u32, Box<dyn std::error::Error>> {
-    let url = format!("{}/v2/status", AVAIL_LIGHT_NODE_HTTP_URL);
-    let client = Client::new();
+/// Fetch the latest block height from one light client HTTP endpoint
+async fn get_latest_block_height_from(
+    conn: &AvailConnection,
+    http_url: &str,
+) -> Result<u32, Box<dyn std::error::Error>> {
+    let url = format!("{}/v2/status", http_url);
 
-    let response = client
+    let timed_out = |e: reqwest::Error| -> Box<dyn std::error::Error> {
+        if e.is_timeout() {
+            format!("Light client at {} did not answer within {:?}", http_url, HTTP_TIMEOUT).into()
+        } else {
+            e.into()
+        }
+    };
+
+    conn.rate_limiter.acquire().await;
+    let response = conn.http
         .get(url)
         .header("User-Agent", "curl/7.88.1")
         .header("Accept", "application/json")
         .send()
-        .await?;
+        .await
+        .map_err(timed_out)?;
 
     let status = response.status();
-    let body = response.text().await?;
+    let body = response.text().await.map_err(timed_out)?;
 
     if status.is_success() {
         let parsed: Value = serde_json::from_str(&body)?;
@@ -127,49 +950,371 @@ pub async fn get_latest_block_height_on_avail() -> Result<u32, Box<dyn std::erro
     }
 }
 
-/// Fetch the block hash for a given block height using the Avail WS client
-pub async fn get_block_hash_by_height_on_avail(block_height: u32) -> Result<H256, ClientError> {
-    let sdk = SDK::new(AVAIL_LIGHT_NODE_WS_URL).await?;
+/// Fetch the hash of the latest finalized block using the Avail WS client
+pub async fn get_finalized_block_hash_on_avail(
+    conn: &AvailConnection
+) -> Result<H256, ClientError> {
+    let block_hash = with_retry(conn, || {
+        rpc::chain::get_finalized_head(&conn.sdk.client)
+    }).await?;
+
+    Ok(block_hash)
+}
 
-    let block_hash = rpc::chain::get_block_hash(&sdk.client, Some(block_height)).await?;
+/// Fetch the height of the latest finalized block using the Avail WS client
+pub async fn get_finalized_block_height_on_avail(
+    conn: &AvailConnection
+) -> Result<u32, ClientError> {
+    let block_hash = get_finalized_block_hash_on_avail(conn).await?;
+    let header = with_retry(conn, || {
+        rpc::chain::get_header(&conn.sdk.client, Some(block_hash))
+    }).await?;
+
+    Ok(header.number)
+}
+
+/// Heights of newly finalized blocks, as the node announces them over the WS
+/// connection. The stream ends, or yields an error, when the subscription
+/// drops; subscribe again to resume.
+pub async fn subscribe_finalized_heights_on_avail(
+    conn: &AvailConnection
+) -> Result<impl Stream<Item = Result<u32, ClientError>> + Send + 'static, ClientError> {
+    let subscription = conn.sdk.client.blocks().subscribe_finalized()
+        .await
+        .map_err(|e| ClientError::from(e.to_string()))?;
+
+    Ok(subscription.map(|block| {
+        block
+            .map(|block| block.number())
+            .map_err(|e| ClientError::from(e.to_string()))
+    }))
+}
+
+/// The height reads scan up to: the latest finalized block, or the latest
+/// block the light client knows of, depending on `conn.read_finality`
+pub async fn get_read_height_on_avail(
+    conn: &AvailConnection
+) -> Result<u32, ClientError> {
+    match conn.read_finality {
+        BlockFinality::Best => get_latest_block_height_on_avail(conn)
+            .await
+            .map_err(|e| ClientError::from(e.to_string())),
+        BlockFinality::Finalized => get_finalized_block_height_on_avail(conn).await,
+    }
+}
+
+/// Fetch the block hash for a given block height using the Avail WS client.
+/// Hashes already cached for finalized heights are returned without a request.
+pub async fn get_block_hash_by_height_on_avail(
+    conn: &AvailConnection,
+    block_height: u32
+) -> Result<H256, ClientError> {
+    let cached = conn.block_hashes.lock().unwrap().get(&block_height).copied();
+    if let Some(block_hash) = cached {
+        return Ok(block_hash);
+    }
+
+    let block_hash = with_retry(conn, || {
+        rpc::chain::get_block_hash(&conn.sdk.client, Some(block_height))
+    }).await?;
 
     Ok(block_hash)
 }
 
-/// Fetch and print blob data for a given app ID from a specific block hash
-pub async fn get_block_data_by_hash_on_avail(
+/// Like `get_block_hash_by_height_on_avail`, but caches the hash when the
+/// height is at or below `finalized_height`. Hashes of unfinalized heights
+/// are never cached since a reorg could still replace them.
+async fn get_block_hash_cached(
+    conn: &AvailConnection,
+    block_height: u32,
+    finalized_height: u32
+) -> Result<H256, ClientError> {
+    let block_hash = get_block_hash_by_height_on_avail(conn, block_height).await?;
+    if block_height <= finalized_height {
+        conn.block_hashes.lock().unwrap().insert(block_height, block_hash);
+    }
+
+    Ok(block_hash)
+}
+
+/// A blob submitted in a block, with the index of the extrinsic carrying it
+#[derive(Debug, Clone)]
+pub struct BlockBlob {
+    pub tx_index: u32,
+    pub data: String,
+}
+
+/// The blobs an app submitted in one block, newest first
+#[derive(Debug, Clone)]
+pub struct BlockData {
+    pub height: u32,
+    pub hash: H256,
+    pub blobs: Vec<BlockBlob>,
+}
+
+/// Where a blob was included on-chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlobLocation {
+    pub block_height: u32,
+    pub block_hash: H256,
+    /// Index of the `submit_data` extrinsic within the block
+    pub tx_index: u32,
+}
+
+/// Fetch the blobs for a given app ID from a specific block hash, newest first
+pub async fn get_block_blobs_by_hash_on_avail(
+    conn: &AvailConnection,
     block_hash: H256,
     app_id: u32
-) -> Result<Vec<String>, ClientError> {
-    let sdk = SDK::new(AVAIL_LIGHT_NODE_WS_URL).await?;
-    let block = Block::new(&sdk.client, block_hash).await?;
+) -> Result<Vec<BlockBlob>, ClientError> {
+    let block = with_retry(conn, || Block::new(&conn.sdk.client, block_hash)).await?;
     let blobs = block.data_submissions(Filter::new().app_id(app_id));
 
+    // A blob that can't be decoded is no database entry, so it is skipped
+    // rather than failing every read that scans this block
     let mut results = Vec::new();
+    let mut skipped = 0;
     for blob in blobs.into_iter().rev() {
-        let blob_data = blob.to_ascii().unwrap();
-        results.push(blob_data);
+        match decode_blob(blob.data) {
+            Ok(data) => results.push(BlockBlob { tx_index: blob.tx_index, data }),
+            Err(e) => {
+                warn!("Skipping blob at extrinsic {} of block {:?}: {}", blob.tx_index, block_hash, e);
+                skipped += 1;
+            }
+        }
     }
+    if skipped > 0 {
+        warn!("Skipped {} undecodable blobs in block {:?}", skipped, block_hash);
+    }
+
     Ok(results)
 }
 
+/// Fetch and print blob data for a given app ID from a specific block hash
+pub async fn get_block_data_by_hash_on_avail(
+    conn: &AvailConnection,
+    block_hash: H256,
+    app_id: u32
+) -> Result<Vec<String>, ClientError> {
+    let blobs = get_block_blobs_by_hash_on_avail(conn, block_hash, app_id).await?;
+
+    Ok(blobs.into_iter().map(|blob| blob.data).collect())
+}
+
+/// Fetch the timestamp of a block, as set by its block author
+pub async fn get_block_timestamp_on_avail(
+    conn: &AvailConnection,
+    block_hash: H256
+) -> Result<DateTime<Utc>, ClientError> {
+    let storage = conn.sdk.client.storage().at(block_hash);
+    let storage_key = avail::storage().timestamp().now();
+
+    let millis = with_retry(conn, || storage.fetch(&storage_key))
+        .await?
+        .ok_or("Block has no timestamp")?;
+
+    DateTime::from_timestamp_millis(millis as i64)
+        .ok_or_else(|| format!("Invalid block timestamp: {}", millis).into())
+}
+
+/// Fetch the free balance, in Planck, of the signing account at the best block.
+/// An account that was never funded has a balance of 0.
+pub async fn get_account_balance_on_avail(
+    conn: &AvailConnection
+) -> Result<u128, ClientError> {
+    let block_hash = with_retry(conn, || conn.sdk.client.best_block_hash()).await?;
+    let storage = conn.sdk.client.storage().at(block_hash);
+    let storage_key = avail::storage().system().account(conn.account()?.public_key().to_account_id());
+
+    let account = with_retry(conn, || storage.fetch(&storage_key)).await?;
+
+    Ok(account.map(|account| account.data.free).unwrap_or_default())
+}
+
+/// Formats a Planck amount as AVAIL, e.g. `1.5 AVAIL`
+pub fn format_avail(planck: u128) -> String {
+    let whole = planck / PLANCK_PER_AVAIL;
+    let fraction = format!("{:018}", planck % PLANCK_PER_AVAIL);
+    let fraction = fraction.trim_end_matches('0');
+
+    if fraction.is_empty() {
+        format!("{} AVAIL", whole)
+    } else {
+        format!("{}.{} AVAIL", whole, fraction)
+    }
+}
+
+/// Like `get_block_blobs_by_hash_on_avail`, but serves and stores the blobs of
+/// finalized blocks in the connection's LRU cache. Unfinalized blocks are
+/// always fetched, so a cache entry can never go stale.
+async fn get_block_blobs_cached(
+    conn: &AvailConnection,
+    block_hash: H256,
+    app_id: u32,
+    finalized: bool
+) -> Result<Vec<BlockBlob>, ClientError> {
+    if finalized {
+        let cached = conn.block_data.lock().unwrap()
+            .as_mut()
+            .and_then(|cache| cache.get(&(block_hash, app_id)).cloned());
+        if let Some(blobs) = cached {
+            return Ok(blobs);
+        }
+    }
+
+    let blobs = get_block_blobs_by_hash_on_avail(conn, block_hash, app_id).await?;
+    if finalized {
+        if let Some(cache) = conn.block_data.lock().unwrap().as_mut() {
+            cache.put((block_hash, app_id), blobs.clone());
+        }
+    }
+
+    Ok(blobs)
+}
+
 /// Fetch blob data for a given app ID from the latest N blocks and return all as a single string
 pub async fn get_data_from_avail_by_app_id(
+    conn: &AvailConnection,
     app_id: u32,
     block_range: u32
 ) -> Result<Vec<String>, ClientError> {
-    let latest_block_height = get_latest_block_height_on_avail()
-        .await
-        .map_err(|e| ClientError::from(e.to_string()))?;
-
-    let mut all_data = vec![];
+    let latest_block_height = get_read_height_on_avail(conn).await?;
 
     let start_block_height = latest_block_height.saturating_sub(block_range);
-    for block_height in (start_block_height..=latest_block_height).rev() {
-        let block_hash = get_block_hash_by_height_on_avail(block_height).await?;
-        let block_blobs = get_block_data_by_hash_on_avail(block_hash, app_id).await?;
-        all_data.extend(block_blobs);
+    get_data_from_avail_by_range(conn, app_id, start_block_height, latest_block_height).await
+}
+
+/// Fetch blob data for a given app ID from every block in `from_height..=to_height`,
+/// newest block first. Up to `conn.concurrency` blocks are fetched at once.
+pub async fn get_data_from_avail_by_range(
+    conn: &AvailConnection,
+    app_id: u32,
+    from_height: u32,
+    to_height: u32
+) -> Result<Vec<String>, ClientError> {
+    let blocks = get_blocks_from_avail_by_range(conn, app_id, from_height, to_height).await?;
+
+    Ok(blocks
+        .into_iter()
+        .flat_map(|block| block.blobs.into_iter().map(|blob| blob.data))
+        .collect())
+}
+
+/// Fetch the blobs for a given app ID from every block in `from_height..=to_height`,
+/// grouped by block, newest block first. Up to `conn.concurrency` blocks are
+/// fetched at once.
+pub async fn get_blocks_from_avail_by_range(
+    conn: &AvailConnection,
+    app_id: u32,
+    from_height: u32,
+    to_height: u32
+) -> Result<Vec<BlockData>, ClientError> {
+    let finalized_height = get_finalized_block_height_on_avail(conn).await?;
+    debug!("Fetching blocks {}..={} for app ID {}", from_height, to_height, app_id);
+
+    let block_count = (to_height + 1).saturating_sub(from_height);
+    let progress = (conn.show_progress && block_count >= PROGRESS_MIN_BLOCKS).then(|| {
+        let bar = ProgressBar::new(block_count as u64);
+        if let Ok(style) = ProgressStyle::with_template("Fetching blocks {pos}/{len} [{bar:30}] {eta}") {
+            bar.set_style(style.progress_chars("=> "));
+        }
+        bar
+    });
+
+    // Borrowed so every fetch can tick the bar
+    let progress_bar = progress.as_ref();
+    let fetched = fetch_newest_first(from_height, to_height, conn.concurrency, |height| async move {
+        let hash = get_block_hash_cached(conn, height, finalized_height).await?;
+        let finalized = height <= finalized_height;
+        let blobs = get_block_blobs_cached(conn, hash, app_id, finalized).await?;
+        debug!("Fetched block {} ({} blobs)", height, blobs.len());
+        if let Some(bar) = progress_bar {
+            bar.inc(1);
+        }
+        Ok::<_, ClientError>(BlockData { height, hash, blobs })
+    }).await;
+    if let Some(bar) = progress {
+        bar.finish_and_clear();
+    }
+
+    fetched
+}
+
+/// Runs `fetch` for every height in `from_height..=to_height`, up to
+/// `concurrency` at once, and returns the results newest height first.
+/// Fetches complete in arbitrary order; callers rely on newest-block-first.
+pub(crate) async fn fetch_newest_first<T, E, F, Fut>(
+    from_height: u32,
+    to_height: u32,
+    concurrency: usize,
+    fetch: F,
+) -> Result<Vec<T>, E>
+where
+    F: Fn(u32) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut fetched: Vec<(u32, T)> = stream::iter(from_height..=to_height)
+        .map(|height| {
+            let fetching = fetch(height);
+            async move { Ok::<_, E>((height, fetching.await?)) }
+        })
+        .buffer_unordered(concurrency.max(1))
+        .try_collect()
+        .await?;
+    fetched.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+
+    Ok(fetched.into_iter().map(|(_, value)| value).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DatabaseClientConfig;
+
+    /// No seed phrase is set for this account, in the environment or `.env`
+    const UNSET_ACCOUNT: Option<&str> = Some("no-such-test-account");
+
+    #[test]
+    fn read_only_session_with_an_app_id_connects_without_a_seed_phrase() {
+        // What `--read-only --app-id 7` configures
+        let config = DatabaseClientConfig::new().read_only(true).app_id(7);
+        let signer = Signer::new(UNSET_ACCOUNT, config.needs_account()).unwrap();
+
+        let Err(e) = signer.keypair() else {
+            panic!("a seed phrase was found for {:?}", UNSET_ACCOUNT);
+        };
+        assert!(e.to_string().contains("AVAIL_SEED_NO_SUCH_TEST_ACCOUNT"));
+    }
+
+    #[test]
+    fn signing_session_fails_up_front_without_a_seed_phrase() {
+        let config = DatabaseClientConfig::new();
+        assert!(config.needs_account());
+        assert!(Signer::new(UNSET_ACCOUNT, config.needs_account()).is_err());
+        assert!(Signer::new(UNSET_ACCOUNT, config.dry_run(true).needs_account()).is_ok());
+    }
+
+    #[tokio::test]
+    async fn concurrent_fetches_come_back_newest_first() {
+        // Delays vary by height, so fetches complete out of order
+        let fetched = fetch_newest_first(1, 12, 4, |height| async move {
+            tokio::time::sleep(Duration::from_millis((height * 5 % 7) as u64)).await;
+            Ok::<_, String>(format!("block {}", height))
+        }).await.unwrap();
+
+        let expected: Vec<String> = (1..=12).rev().map(|height| format!("block {}", height)).collect();
+        assert_eq!(fetched, expected);
     }
 
-    Ok(all_data)
+    #[tokio::test]
+    async fn a_failed_fetch_fails_the_whole_range() {
+        let fetched = fetch_newest_first(1, 6, 3, |height| async move {
+            if height == 4 {
+                return Err(format!("block {} unavailable", height));
+            }
+            Ok(height)
+        }).await;
+
+        assert_eq!(fetched, Err("block 4 unavailable".to_string()));
+    }
 }
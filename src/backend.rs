@@ -0,0 +1,306 @@
+use async_trait::async_trait;
+use avail_rust::prelude::H256;
+use chrono::{DateTime, Utc};
+use futures::stream::{BoxStream, StreamExt};
+use std::sync::{Arc, Mutex};
+
+use crate::avail::{self, AvailConnection, BlockBlob, BlockData, SubmissionReceipt};
+use crate::schema::DatabaseError;
+
+/// The operations `DatabaseClient` needs from the data availability layer
+#[async_trait]
+pub trait AvailBackend: Send + Sync {
+    /// Submit a blob under the given app ID
+    async fn submit_data(&self, app_id: u32, data: String) -> Result<SubmissionReceipt, DatabaseError>;
+
+    /// The latest block height reads should scan up to
+    async fn get_latest_block_height(&self) -> Result<u32, DatabaseError>;
+
+    /// The blobs for the given app ID of every block in `from_height..=to_height`,
+    /// grouped by block, newest first
+    async fn get_blocks_by_range(
+        &self,
+        app_id: u32,
+        from_height: u32,
+        to_height: u32,
+    ) -> Result<Vec<BlockData>, DatabaseError>;
+
+    /// Blob data for the given app ID from every block in `from_height..=to_height`,
+    /// newest first
+    async fn get_data_by_range(
+        &self,
+        app_id: u32,
+        from_height: u32,
+        to_height: u32,
+    ) -> Result<Vec<String>, DatabaseError> {
+        let blocks = self.get_blocks_by_range(app_id, from_height, to_height).await?;
+
+        Ok(blocks
+            .into_iter()
+            .flat_map(|block| block.blobs.into_iter().map(|blob| blob.data))
+            .collect())
+    }
+
+    /// The on-chain timestamp of a block
+    async fn get_block_timestamp(&self, block_hash: H256) -> Result<DateTime<Utc>, DatabaseError>;
+
+    /// Blob data for the given app ID from the latest `block_range` blocks, newest first
+    async fn get_data_by_app_id(
+        &self,
+        app_id: u32,
+        block_range: u32,
+    ) -> Result<Vec<String>, DatabaseError> {
+        let latest_block_height = self.get_latest_block_height().await?;
+        let from_height = latest_block_height.saturating_sub(block_range);
+
+        self.get_data_by_range(app_id, from_height, latest_block_height).await
+    }
+
+    /// Free balance, in Planck, of the account paying for submissions, or
+    /// `None` for backends where submissions cost nothing or when there is no
+    /// account paying for them
+    async fn get_account_balance(&self) -> Result<Option<u128>, DatabaseError> {
+        Ok(None)
+    }
+
+    /// Heights of newly finalized blocks as they are produced, or `None` for
+    /// backends that can't push them, whose watchers poll instead
+    async fn subscribe_finalized_heights(
+        &self,
+    ) -> Result<Option<BoxStream<'static, Result<u32, DatabaseError>>>, DatabaseError> {
+        Ok(None)
+    }
+
+    /// Drops any chain data the backend has cached. Backends without a cache
+    /// don't need to override this.
+    fn clear_cache(&self) {}
+}
+
+/// Backend talking to a live Avail node through the functions in `avail.rs`
+pub struct AvailRpcBackend {
+    conn: AvailConnection,
+}
+
+impl AvailRpcBackend {
+    pub fn new(conn: AvailConnection) -> Self {
+        Self { conn }
+    }
+}
+
+#[async_trait]
+impl AvailBackend for AvailRpcBackend {
+    async fn submit_data(&self, app_id: u32, data: String) -> Result<SubmissionReceipt, DatabaseError> {
+        avail::submit_data_to_avail_by_app_id(&self.conn, app_id, data)
+            .await
+            .map_err(|e| DatabaseError::AvailError(e.to_string()))
+    }
+
+    async fn get_latest_block_height(&self) -> Result<u32, DatabaseError> {
+        avail::get_read_height_on_avail(&self.conn)
+            .await
+            .map_err(|e| DatabaseError::AvailError(e.to_string()))
+    }
+
+    async fn get_blocks_by_range(
+        &self,
+        app_id: u32,
+        from_height: u32,
+        to_height: u32,
+    ) -> Result<Vec<BlockData>, DatabaseError> {
+        avail::get_blocks_from_avail_by_range(&self.conn, app_id, from_height, to_height)
+            .await
+            .map_err(|e| DatabaseError::AvailError(e.to_string()))
+    }
+
+    async fn get_data_by_range(
+        &self,
+        app_id: u32,
+        from_height: u32,
+        to_height: u32,
+    ) -> Result<Vec<String>, DatabaseError> {
+        avail::get_data_from_avail_by_range(&self.conn, app_id, from_height, to_height)
+            .await
+            .map_err(|e| DatabaseError::AvailError(e.to_string()))
+    }
+
+    async fn get_block_timestamp(&self, block_hash: H256) -> Result<DateTime<Utc>, DatabaseError> {
+        avail::get_block_timestamp_on_avail(&self.conn, block_hash)
+            .await
+            .map_err(|e| DatabaseError::AvailError(e.to_string()))
+    }
+
+    async fn get_data_by_app_id(
+        &self,
+        app_id: u32,
+        block_range: u32,
+    ) -> Result<Vec<String>, DatabaseError> {
+        avail::get_data_from_avail_by_app_id(&self.conn, app_id, block_range)
+            .await
+            .map_err(|e| DatabaseError::AvailError(e.to_string()))
+    }
+
+    async fn get_account_balance(&self) -> Result<Option<u128>, DatabaseError> {
+        // A read-only or dry-run connection may have no seed phrase to load
+        if self.conn.account().is_err() {
+            return Ok(None);
+        }
+        avail::get_account_balance_on_avail(&self.conn)
+            .await
+            .map(Some)
+            .map_err(|e| DatabaseError::AvailError(e.to_string()))
+    }
+
+    async fn subscribe_finalized_heights(
+        &self,
+    ) -> Result<Option<BoxStream<'static, Result<u32, DatabaseError>>>, DatabaseError> {
+        let heights = avail::subscribe_finalized_heights_on_avail(&self.conn)
+            .await
+            .map_err(|e| DatabaseError::AvailError(e.to_string()))?;
+
+        Ok(Some(
+            heights
+                .map(|height| height.map_err(|e| DatabaseError::AvailError(e.to_string())))
+                .boxed(),
+        ))
+    }
+
+    fn clear_cache(&self) {
+        self.conn.clear_block_hash_cache();
+        self.conn.clear_block_data_cache();
+    }
+}
+
+/// In-memory backend for tests and local experiments: submissions are
+/// included in new blocks starting after `start_height`, each in its own
+/// unless `blobs_per_block` lets several share one. Clones share one chain,
+/// so several clients can open the same database.
+#[derive(Clone)]
+pub struct MemoryBackend {
+    chain: Arc<Mutex<MemoryChain>>,
+    /// Maximum number of blocks fetched concurrently by `get_blocks_by_range`
+    concurrency: usize,
+}
+
+#[derive(Default)]
+struct MemoryChain {
+    height: u32,
+    /// How many submissions a block holds before the next one starts a new block
+    blobs_per_block: usize,
+    /// Blobs in the block at `height` so far, the next one's `tx_index`
+    open_block_len: usize,
+    /// `(block_height, tx_index, app_id, blob)` in submission order
+    blobs: Vec<(u32, u32, u32, String)>,
+    /// `(block_height, timestamp)` of every block produced so far
+    timestamps: Vec<(u32, DateTime<Utc>)>,
+}
+
+impl MemoryBackend {
+    pub fn new(start_height: u32) -> Self {
+        Self {
+            chain: Arc::new(Mutex::new(MemoryChain {
+                height: start_height,
+                blobs_per_block: 1,
+                ..MemoryChain::default()
+            })),
+            concurrency: 1,
+        }
+    }
+
+    /// Includes up to `blobs_per_block` submissions in each block, at
+    /// increasing extrinsic indexes, as a busy node would. Applies to every
+    /// clone, since they share the chain.
+    pub fn blobs_per_block(self, blobs_per_block: usize) -> Self {
+        self.chain.lock().unwrap().blobs_per_block = blobs_per_block.max(1);
+        self
+    }
+
+    /// Fetches up to `concurrency` blocks at once, like
+    /// `AvailConnection::concurrency`. Only applies to this clone.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Every blob submitted so far, oldest first
+    pub fn blobs(&self) -> Vec<String> {
+        let chain = self.chain.lock().unwrap();
+        chain.blobs.iter().map(|(_, _, _, blob)| blob.clone()).collect()
+    }
+}
+
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+#[async_trait]
+impl AvailBackend for MemoryBackend {
+    async fn submit_data(&self, app_id: u32, data: String) -> Result<SubmissionReceipt, DatabaseError> {
+        let mut chain = self.chain.lock().unwrap();
+        if chain.open_block_len == 0 || chain.open_block_len >= chain.blobs_per_block {
+            chain.height += 1;
+            chain.open_block_len = 0;
+            let height = chain.height;
+            chain.timestamps.push((height, Utc::now()));
+        }
+        let height = chain.height;
+        let tx_index = chain.open_block_len as u32;
+        chain.open_block_len += 1;
+        chain.blobs.push((height, tx_index, app_id, data.clone()));
+
+        Ok(SubmissionReceipt {
+            tx_hash: H256::from_low_u64_be(chain.blobs.len() as u64),
+            block_hash: H256::from_low_u64_be(height as u64),
+            block_height: height,
+            data,
+        })
+    }
+
+    async fn get_latest_block_height(&self) -> Result<u32, DatabaseError> {
+        Ok(self.chain.lock().unwrap().height)
+    }
+
+    async fn get_blocks_by_range(
+        &self,
+        app_id: u32,
+        from_height: u32,
+        to_height: u32,
+    ) -> Result<Vec<BlockData>, DatabaseError> {
+        let chain = &self.chain;
+
+        let blocks = avail::fetch_newest_first(from_height, to_height, self.concurrency, |height| async move {
+            // Yield a varying number of times so concurrent fetches finish
+            // out of order, as they do against a node
+            for _ in 0..height % 3 {
+                tokio::task::yield_now().await;
+            }
+            let chain = chain.lock().unwrap();
+            let blobs: Vec<BlockBlob> = chain
+                .blobs
+                .iter()
+                .filter(|(block_height, _, id, _)| *block_height == height && *id == app_id)
+                .map(|(_, tx_index, _, blob)| BlockBlob { tx_index: *tx_index, data: blob.clone() })
+                .collect();
+
+            Ok::<_, DatabaseError>(
+                (!blobs.is_empty()).then(|| BlockData { height, hash: H256::from_low_u64_be(height as u64), blobs }),
+            )
+        })
+        .await?;
+
+        // Blocks without blobs for this app are left out
+        Ok(blocks.into_iter().flatten().collect())
+    }
+
+    async fn get_block_timestamp(&self, block_hash: H256) -> Result<DateTime<Utc>, DatabaseError> {
+        let chain = self.chain.lock().unwrap();
+
+        chain
+            .timestamps
+            .iter()
+            .find(|(height, _)| H256::from_low_u64_be(*height as u64) == block_hash)
+            .map(|(_, timestamp)| *timestamp)
+            .ok_or_else(|| DatabaseError::NotFound(format!("block {:?}", block_hash)))
+    }
+}
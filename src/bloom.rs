@@ -0,0 +1,97 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Target false positive rate of filters built with `BloomFilter::for_keys`
+const FALSE_POSITIVE_RATE: f64 = 0.01;
+
+/// A Bloom filter over record keys. `contains` never returns `false` for an
+/// inserted key, but may return `true` for a key that was never inserted.
+///
+/// The filter is stored on-chain and read by other clients, so key positions
+/// come from FNV-1a, whose output is fixed, rather than from `std`'s hasher,
+/// which may change between Rust releases.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BloomFilter {
+    /// Bit array, stored base64-encoded
+    #[serde(serialize_with = "serialize_bits", deserialize_with = "deserialize_bits")]
+    bits: Vec<u8>,
+    /// Number of bit positions set per key
+    hashes: u32,
+}
+
+impl BloomFilter {
+    /// Creates an empty filter sized for `capacity` keys at a 1% false
+    /// positive rate
+    pub fn with_capacity(capacity: usize) -> Self {
+        let capacity = capacity.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+
+        let bit_count = (-capacity * FALSE_POSITIVE_RATE.ln() / (ln2 * ln2)).ceil() as usize;
+        let hashes = ((bit_count as f64 / capacity) * ln2).round().max(1.0) as u32;
+
+        Self {
+            bits: vec![0; bit_count.div_ceil(8).max(1)],
+            hashes,
+        }
+    }
+
+    /// Builds a filter holding every key in `keys`
+    pub fn for_keys<'a>(keys: impl ExactSizeIterator<Item = &'a str>) -> Self {
+        let mut filter = Self::with_capacity(keys.len());
+        for key in keys {
+            filter.insert(key);
+        }
+        filter
+    }
+
+    pub fn insert(&mut self, key: &str) {
+        for bit in self.bit_positions(key) {
+            self.bits[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    /// Whether `key` may have been inserted. `false` means it definitely was not.
+    pub fn contains(&self, key: &str) -> bool {
+        self.bit_positions(key).all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    /// The `hashes` bit positions of `key`, derived from two hashes by
+    /// double hashing
+    fn bit_positions(&self, key: &str) -> impl Iterator<Item = usize> + '_ {
+        let bit_count = (self.bits.len() * 8) as u64;
+        let h1 = fnv1a(key.as_bytes());
+        // Odd, so the positions cycle through every bit
+        let h2 = splitmix64(h1) | 1;
+
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % bit_count) as usize)
+    }
+}
+
+/// 64-bit FNV-1a
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// SplitMix64 finalizer, to derive a second independent-looking hash
+fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+fn serialize_bits<S: Serializer>(bits: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&BASE64.encode(bits))
+}
+
+fn deserialize_bits<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let encoded = String::deserialize(deserializer)?;
+    let bits = BASE64.decode(encoded).map_err(serde::de::Error::custom)?;
+    if bits.is_empty() {
+        return Err(serde::de::Error::custom("Bloom filter has no bits"));
+    }
+
+    Ok(bits)
+}
@@ -0,0 +1,445 @@
+use dotenvy::dotenv;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::warn;
+
+use crate::avail::{
+    AvailConnection, AvailEndpoints, BlockFinality, Network, RetryConfig, DEFAULT_BLOCK_CACHE_SIZE,
+    DEFAULT_COMPRESSION_THRESHOLD, DEFAULT_FETCH_CONCURRENCY, DEFAULT_INCLUSION_TIMEOUT, DEFAULT_MIN_BALANCE,
+    DEFAULT_REQUESTS_PER_SECOND, HIGH_FETCH_CONCURRENCY, RateLimiter,
+};
+use crate::schema::DatabaseError;
+
+/// Number of blocks searched back from the current height when discovering an existing database
+pub const DEFAULT_BLOCK_RANGE: u32 = 10;
+
+/// Environment variable overriding `DEFAULT_BLOCK_RANGE`
+pub const BLOCK_RANGE_ENV: &str = "AVAIL_BLOCK_RANGE";
+
+/// The default block range: `AVAIL_BLOCK_RANGE` from the environment (or
+/// .env) if set to a valid number, `DEFAULT_BLOCK_RANGE` otherwise
+pub fn default_block_range() -> u32 {
+    dotenv().ok();
+
+    match std::env::var(BLOCK_RANGE_ENV) {
+        Ok(value) => value.trim().parse().unwrap_or_else(|_| {
+            warn!(
+                "Ignoring {}='{}': not a block count, using {}",
+                BLOCK_RANGE_ENV, value, DEFAULT_BLOCK_RANGE
+            );
+            DEFAULT_BLOCK_RANGE
+        }),
+        Err(_) => DEFAULT_BLOCK_RANGE,
+    }
+}
+
+/// Environment variable holding an app ID to use directly instead of
+/// resolving the app name
+pub const APP_ID_ENV: &str = "AVAIL_APP_ID";
+
+/// The app ID in `AVAIL_APP_ID` from the environment (or .env), or `None`
+/// when it is not set. Unlike `AVAIL_BLOCK_RANGE`, an invalid value is an
+/// error rather than ignored: falling back to the app name could open a
+/// different database.
+pub fn app_id_from_env() -> Result<Option<u32>, DatabaseError> {
+    dotenv().ok();
+
+    match std::env::var(APP_ID_ENV) {
+        Ok(value) => value.trim().parse().map(Some).map_err(|_| {
+            DatabaseError::InvalidInput(format!("{}='{}' is not a valid app ID", APP_ID_ENV, value))
+        }),
+        Err(_) => Ok(None),
+    }
+}
+
+/// The settings a `--config` TOML file may hold, each optional. Every key
+/// maps to the `DatabaseClientConfig` field of the same name; durations are
+/// in seconds, or in milliseconds for keys ending in `_ms`, and a
+/// `compression_threshold` of 0 disables compression. `min_balance` is a
+/// string of digits, since Planck amounts outgrow TOML's 64-bit integers.
+/// Unknown keys are rejected so a typo can't silently leave a default in
+/// place.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    network: Option<String>,
+    block_range: Option<u32>,
+    retry_attempts: Option<u32>,
+    retry_base_delay_ms: Option<u64>,
+    requests_per_second: Option<u32>,
+    concurrency: Option<usize>,
+    compression_threshold: Option<usize>,
+    inclusion_timeout_secs: Option<u64>,
+    block_cache_size: Option<usize>,
+    max_blob_size: Option<usize>,
+    /// `finalized` or `best`
+    read_finality: Option<String>,
+    strict: Option<bool>,
+    state_file: Option<PathBuf>,
+    metadata_file: Option<PathBuf>,
+    account: Option<String>,
+    dry_run: Option<bool>,
+    read_only: Option<bool>,
+    min_balance: Option<String>,
+    warn_on_overwrite: Option<bool>,
+}
+
+/// Settings for a `DatabaseClient`, built up with chained setters,
+/// e.g. `DatabaseClientConfig::new().app_id(447).block_range(100)`
+#[derive(Debug, Clone)]
+pub struct DatabaseClientConfig {
+    pub app_id: u32,
+    /// How many blocks to look back when discovering existing database
+    /// metadata; defaults to `default_block_range()`
+    pub block_range: u32,
+    pub network: Network,
+    /// Total attempts for transient RPC failures, including the first one
+    pub retry_attempts: u32,
+    /// Delay before the first retry, doubled after every failed attempt
+    pub retry_base_delay: Duration,
+    /// Most requests per second sent to the node and light client, retries
+    /// included; 0 disables the limit
+    pub requests_per_second: u32,
+    /// How many blocks a scan fetches at once; at least 1
+    pub concurrency: usize,
+    /// Blobs larger than this many bytes are gzip-compressed when that makes
+    /// them smaller; `None` disables compression
+    pub compression_threshold: Option<usize>,
+    /// How long a submission waits for its transaction to be included before
+    /// failing, so a stalled network can't block a write forever
+    pub inclusion_timeout: Duration,
+    /// How many finalized blocks' blobs to keep in memory between reads; 0 disables the cache
+    pub block_cache_size: usize,
+    /// Largest blob in bytes the client submits; `None` uses the network's limit
+    pub max_blob_size: Option<usize>,
+    /// Whether reads stop at the latest finalized block (the default) or
+    /// include blocks that could still be reorged away
+    pub read_finality: BlockFinality,
+    /// Fail reads that find blobs which are not database entries, instead of
+    /// skipping them with a warning
+    pub strict: bool,
+    /// File the scan state is saved to after every read and resumed from on
+    /// startup, so a new session only fetches blocks produced since; `None`
+    /// rescans from the database start
+    pub state_file: Option<PathBuf>,
+    /// File the latest metadata of each database is saved to, keyed by app
+    /// ID, so opening a database it lists checks one block instead of
+    /// running discovery; `None` always discovers
+    pub metadata_file: Option<PathBuf>,
+    /// Named account signing every write, whose seed phrase is read from
+    /// `AVAIL_SEED_<NAME>`; `None` uses `AVAIL_SEED_PHRASE`
+    pub account: Option<String>,
+    /// Log every submission instead of sending it, so nothing is written
+    /// and no fees are spent
+    pub dry_run: bool,
+    /// Refuse every write with `DatabaseError::ReadOnly`, metadata included.
+    /// A database that doesn't exist yet is not created: the client opens
+    /// an empty one that only lives in memory.
+    pub read_only: bool,
+    /// Balance in Planck below which opening the database warns that the
+    /// account needs funding
+    pub min_balance: u128,
+    /// Whether `add_record` and `add_records` check if a key already exists
+    /// and warn that it is being overwritten. Costs a read per add, so it is
+    /// off by default; the CLI turns it on.
+    pub warn_on_overwrite: bool,
+}
+
+impl Default for DatabaseClientConfig {
+    fn default() -> Self {
+        Self {
+            app_id: 0,
+            block_range: default_block_range(),
+            network: Network::default(),
+            retry_attempts: RetryConfig::default().max_attempts,
+            retry_base_delay: RetryConfig::default().base_delay,
+            requests_per_second: DEFAULT_REQUESTS_PER_SECOND,
+            concurrency: DEFAULT_FETCH_CONCURRENCY,
+            compression_threshold: Some(DEFAULT_COMPRESSION_THRESHOLD),
+            inclusion_timeout: DEFAULT_INCLUSION_TIMEOUT,
+            block_cache_size: DEFAULT_BLOCK_CACHE_SIZE,
+            max_blob_size: None,
+            read_finality: BlockFinality::default(),
+            strict: false,
+            state_file: None,
+            metadata_file: None,
+            account: None,
+            dry_run: false,
+            read_only: false,
+            min_balance: DEFAULT_MIN_BALANCE,
+            warn_on_overwrite: false,
+        }
+    }
+}
+
+impl DatabaseClientConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn app_id(mut self, app_id: u32) -> Self {
+        self.app_id = app_id;
+        self
+    }
+
+    pub fn block_range(mut self, block_range: u32) -> Self {
+        self.block_range = block_range;
+        self
+    }
+
+    pub fn network(mut self, network: Network) -> Self {
+        self.network = network;
+        self
+    }
+
+    pub fn retry_attempts(mut self, retry_attempts: u32) -> Self {
+        self.retry_attempts = retry_attempts;
+        self
+    }
+
+    pub fn retry_base_delay(mut self, retry_base_delay: Duration) -> Self {
+        self.retry_base_delay = retry_base_delay;
+        self
+    }
+
+    pub fn requests_per_second(mut self, requests_per_second: u32) -> Self {
+        self.requests_per_second = requests_per_second;
+        self
+    }
+
+    /// Sets how many blocks a scan fetches at once. 0 is treated as 1.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    pub fn compression_threshold(mut self, compression_threshold: Option<usize>) -> Self {
+        self.compression_threshold = compression_threshold;
+        self
+    }
+
+    pub fn inclusion_timeout(mut self, inclusion_timeout: Duration) -> Self {
+        self.inclusion_timeout = inclusion_timeout;
+        self
+    }
+
+    pub fn block_cache_size(mut self, block_cache_size: usize) -> Self {
+        self.block_cache_size = block_cache_size;
+        self
+    }
+
+    pub fn max_blob_size(mut self, max_blob_size: usize) -> Self {
+        self.max_blob_size = Some(max_blob_size);
+        self
+    }
+
+    pub fn read_finality(mut self, read_finality: BlockFinality) -> Self {
+        self.read_finality = read_finality;
+        self
+    }
+
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn state_file(mut self, state_file: impl Into<PathBuf>) -> Self {
+        self.state_file = Some(state_file.into());
+        self
+    }
+
+    pub fn metadata_file(mut self, metadata_file: impl Into<PathBuf>) -> Self {
+        self.metadata_file = Some(metadata_file.into());
+        self
+    }
+
+    pub fn account(mut self, account: impl Into<String>) -> Self {
+        self.account = Some(account.into());
+        self
+    }
+
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Whether sessions with this configuration sign transactions, so
+    /// connecting must load the account; read-only and dry-run ones don't
+    pub fn needs_account(&self) -> bool {
+        !(self.read_only || self.dry_run)
+    }
+
+    pub fn min_balance(mut self, min_balance: u128) -> Self {
+        self.min_balance = min_balance;
+        self
+    }
+
+    pub fn warn_on_overwrite(mut self, warn_on_overwrite: bool) -> Self {
+        self.warn_on_overwrite = warn_on_overwrite;
+        self
+    }
+
+    /// Reads settings from the TOML file at `path` on top of the defaults.
+    /// Settings the file leaves out keep their default, and the app ID is
+    /// never read from it. Fails with `InvalidInput` on unknown keys and
+    /// invalid values.
+    pub fn from_file(path: &Path) -> Result<Self, DatabaseError> {
+        Self::default().with_file(path)
+    }
+
+    /// Like `from_file`, but on top of these settings instead of the defaults
+    pub fn with_file(self, path: &Path) -> Result<Self, DatabaseError> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| DatabaseError::IoError(format!("Failed to read '{}': {}", path.display(), e)))?;
+        let file: ConfigFile = toml::from_str(&contents)
+            .map_err(|e| DatabaseError::InvalidInput(format!("Invalid config file '{}': {}", path.display(), e)))?;
+        let invalid = |key: &str, value: &str| {
+            DatabaseError::InvalidInput(format!("Invalid config file '{}': bad {} '{}'", path.display(), key, value))
+        };
+
+        let mut config = self;
+        if let Some(network) = file.network {
+            config.network = network.parse().map_err(|_| invalid("network", &network))?;
+        }
+        if let Some(block_range) = file.block_range {
+            config.block_range = block_range;
+        }
+        if let Some(retry_attempts) = file.retry_attempts {
+            config.retry_attempts = retry_attempts;
+        }
+        if let Some(millis) = file.retry_base_delay_ms {
+            config.retry_base_delay = Duration::from_millis(millis);
+        }
+        if let Some(requests_per_second) = file.requests_per_second {
+            config.requests_per_second = requests_per_second;
+        }
+        if let Some(concurrency) = file.concurrency {
+            if concurrency == 0 {
+                return Err(invalid("concurrency", "0"));
+            }
+            config.concurrency = concurrency;
+        }
+        if let Some(threshold) = file.compression_threshold {
+            config.compression_threshold = (threshold > 0).then_some(threshold);
+        }
+        if let Some(secs) = file.inclusion_timeout_secs {
+            config.inclusion_timeout = Duration::from_secs(secs);
+        }
+        if let Some(block_cache_size) = file.block_cache_size {
+            config.block_cache_size = block_cache_size;
+        }
+        if let Some(max_blob_size) = file.max_blob_size {
+            config.max_blob_size = Some(max_blob_size);
+        }
+        if let Some(read_finality) = file.read_finality {
+            config.read_finality = match read_finality.as_str() {
+                "finalized" => BlockFinality::Finalized,
+                "best" => BlockFinality::Best,
+                _ => return Err(invalid("read_finality", &read_finality)),
+            };
+        }
+        if let Some(strict) = file.strict {
+            config.strict = strict;
+        }
+        if let Some(state_file) = file.state_file {
+            config.state_file = Some(state_file);
+        }
+        if let Some(metadata_file) = file.metadata_file {
+            config.metadata_file = Some(metadata_file);
+        }
+        if let Some(account) = file.account {
+            config.account = Some(account);
+        }
+        if let Some(dry_run) = file.dry_run {
+            config.dry_run = dry_run;
+        }
+        if let Some(read_only) = file.read_only {
+            config.read_only = read_only;
+        }
+        if let Some(min_balance) = file.min_balance {
+            config.min_balance = min_balance.trim().parse().map_err(|_| invalid("min_balance", &min_balance))?;
+        }
+        if let Some(warn_on_overwrite) = file.warn_on_overwrite {
+            config.warn_on_overwrite = warn_on_overwrite;
+        }
+
+        Ok(config)
+    }
+
+    /// Opens a connection to the configured network, signing with the
+    /// configured account and honoring the `AVAIL_HTTP_URL`/`AVAIL_WS_URL`
+    /// overrides. The account is only required when `needs_account`.
+    pub async fn connect(&self) -> Result<AvailConnection, DatabaseError> {
+        let endpoints = AvailEndpoints::from_env(self.network)
+            .map_err(|e| DatabaseError::AvailError(e.to_string()))?;
+
+        let mut conn = AvailConnection::connect(self.network, endpoints, self.account.as_deref(), self.needs_account())
+            .await
+            .map_err(|e| DatabaseError::AvailError(e.to_string()))?;
+        self.configure(&mut conn);
+
+        Ok(conn)
+    }
+
+    /// Applies the connection-level settings to a connection opened
+    /// separately, e.g. by a caller reporting its own connection errors
+    pub fn configure(&self, conn: &mut AvailConnection) {
+        conn.retry.max_attempts = self.retry_attempts;
+        conn.retry.base_delay = self.retry_base_delay;
+        conn.rate_limiter = RateLimiter::new(self.requests_per_second);
+        conn.concurrency = self.concurrency.max(1);
+        if conn.concurrency > HIGH_FETCH_CONCURRENCY {
+            warn!(
+                "Fetching {} blocks at once: public nodes may throttle or ban the client above {}",
+                conn.concurrency, HIGH_FETCH_CONCURRENCY
+            );
+        }
+        conn.compression_threshold = self.compression_threshold;
+        conn.inclusion_timeout = self.inclusion_timeout;
+        conn.set_block_cache_size(self.block_cache_size);
+        conn.read_finality = self.read_finality;
+        conn.dry_run = self.dry_run;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `contents` to a config file of its own and loads it
+    fn load(name: &str, contents: &str) -> Result<DatabaseClientConfig, DatabaseError> {
+        let path = std::env::temp_dir().join(format!("dadb-config-{}-{}.toml", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        let config = DatabaseClientConfig::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        config
+    }
+
+    #[test]
+    fn min_balance_beyond_64_bits_loads() {
+        let config = load("min-balance", "min_balance = \"100000000000000000000000\"\n").unwrap();
+        assert_eq!(config.min_balance, 100_000_000_000_000_000_000_000);
+
+        assert!(matches!(load("min-balance-bad", "min_balance = \"1 AVAIL\"\n"), Err(DatabaseError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn readme_example_loads() {
+        let readme = include_str!("../README.md");
+        let section = &readme[readme.find("### Config File").unwrap()..];
+        let start = section.find("```toml\n").unwrap() + "```toml\n".len();
+        let example = &section[start..start + section[start..].find("```").unwrap()];
+
+        let config = load("readme", example).unwrap();
+        assert_eq!(config.network, Network::Mainnet);
+        assert_eq!(config.retry_base_delay, Duration::from_millis(200));
+        assert_eq!(config.min_balance, 1_000_000_000_000_000_000);
+        assert_eq!(config.account.as_deref(), Some("work"));
+    }
+}
@@ -0,0 +1,81 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+
+use crate::schema::DatabaseError;
+
+/// Environment variable holding the base64-encoded 32-byte AES-256 key
+pub const ENCRYPTION_KEY_ENV: &str = "ENCRYPTION_KEY";
+
+/// Prefix marking a `Record::value` as `base64(nonce || ciphertext)`
+pub const ENCRYPTED_VALUE_PREFIX: &str = "enc:aes256gcm:";
+
+/// AES-GCM nonce length in bytes
+const NONCE_LEN: usize = 12;
+
+/// Encrypts and decrypts record values with AES-256-GCM
+pub struct ValueCipher {
+    cipher: Aes256Gcm,
+}
+
+impl ValueCipher {
+    /// Creates a cipher from a base64-encoded 32-byte key
+    pub fn new(encoded_key: &str) -> Result<Self, DatabaseError> {
+        let key = BASE64
+            .decode(encoded_key.trim())
+            .map_err(|e| DatabaseError::InvalidInput(format!("{} is not valid base64: {}", ENCRYPTION_KEY_ENV, e)))?;
+
+        let cipher = Aes256Gcm::new_from_slice(&key).map_err(|_| {
+            DatabaseError::InvalidInput(format!("{} must decode to 32 bytes, got {}", ENCRYPTION_KEY_ENV, key.len()))
+        })?;
+
+        Ok(Self { cipher })
+    }
+
+    /// Reads the key from `ENCRYPTION_KEY`. Returns `None` when it is not set,
+    /// in which case values are stored and read as plain text.
+    pub fn from_env() -> Result<Option<Self>, DatabaseError> {
+        match std::env::var(ENCRYPTION_KEY_ENV) {
+            Ok(key) => Self::new(&key).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Encrypts `value` under a fresh random nonce, prepended to the ciphertext
+    pub fn encrypt(&self, value: &str) -> Result<String, DatabaseError> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, value.as_bytes())
+            .map_err(|e| DatabaseError::SerializationError(format!("Failed to encrypt value: {}", e)))?;
+
+        let mut payload = nonce.to_vec();
+        payload.extend_from_slice(&ciphertext);
+
+        Ok(format!("{}{}", ENCRYPTED_VALUE_PREFIX, BASE64.encode(payload)))
+    }
+
+    /// Decrypts a value produced by `encrypt`. Values without the marker
+    /// prefix were written unencrypted and are returned unchanged.
+    pub fn decrypt(&self, value: &str) -> Result<String, DatabaseError> {
+        let Some(encoded) = value.strip_prefix(ENCRYPTED_VALUE_PREFIX) else {
+            return Ok(value.to_string());
+        };
+
+        let payload = BASE64
+            .decode(encoded)
+            .map_err(|e| DatabaseError::DecryptionError(format!("malformed ciphertext: {}", e)))?;
+        if payload.len() < NONCE_LEN {
+            return Err(DatabaseError::DecryptionError("ciphertext is shorter than its nonce".to_string()));
+        }
+
+        let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| DatabaseError::DecryptionError("wrong key or tampered value".to_string()))?;
+
+        String::from_utf8(plaintext)
+            .map_err(|e| DatabaseError::DecryptionError(format!("plaintext is not valid UTF-8: {}", e)))
+    }
+}
@@ -1,186 +1,2472 @@
-use serde_json;
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
 
-use crate::avail;
-use crate::schema::{DatabaseError, DatabaseMetadata, Record};
+use futures::future;
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::avail::{self, BlobLocation, BlockData, SubmissionReceipt};
+use crate::backend::{AvailBackend, AvailRpcBackend};
+use crate::bloom::BloomFilter;
+use crate::config::DatabaseClientConfig;
+use crate::crypto::ValueCipher;
+use crate::schema::{
+    validate_key, Blob, DatabaseError, DatabaseMetadata, DbStats, InclusionProof, KeyFilter, MetadataReport,
+    Record, RecordPage, Snapshot,
+};
 
 pub struct DatabaseClient {
+    backend: Box<dyn AvailBackend>,
     app_id: u32,
     metadata: Option<DatabaseMetadata>,
-    block_range: Option<u32>,
+    block_range: u32,
+    state: Mutex<ScanState>,
+    /// Set when `ENCRYPTION_KEY` is present: values are encrypted before
+    /// submission and decrypted on read
+    cipher: Option<ValueCipher>,
+    /// Largest blob in bytes `submit` lets through to the backend
+    max_blob_size: usize,
+    /// Whether unrecognized blobs fail reads instead of being skipped
+    strict: bool,
+    /// Where the scan state is persisted between sessions, if anywhere
+    state_file: Option<PathBuf>,
+    /// Where the latest metadata of each database is remembered between
+    /// sessions, if anywhere
+    metadata_file: Option<PathBuf>,
+    /// Whether `add_record` warns when it replaces a live key
+    warn_on_overwrite: bool,
+    /// Whether every submission is refused
+    read_only: bool,
+}
+
+/// How fresh a read must be
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReadConsistency {
+    /// Scan up to the latest readable block before answering, so every
+    /// write included by then is seen
+    #[default]
+    Strong,
+    /// Answer from the state cached by the previous read without contacting
+    /// the chain. Writes included since that read, by this client or any
+    /// other, are not seen until the next `Strong` read. Falls back to a
+    /// `Strong` read when nothing has been cached yet.
+    Cached,
+}
+
+/// Which live records a listing keeps. Bounds left `None` keep everything,
+/// so the default filter keeps every record.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RecordFilter {
+    /// Only records created strictly after this time
+    pub created_after: Option<chrono::DateTime<chrono::Utc>>,
+    /// Only records created strictly before this time
+    pub created_before: Option<chrono::DateTime<chrono::Utc>>,
+    /// Only records carrying this tag
+    pub tag: Option<String>,
+}
+
+impl RecordFilter {
+    /// Whether `record` is within every bound that is set
+    pub fn matches(&self, record: &Record) -> bool {
+        self.created_after.is_none_or(|after| record.created_at > after)
+            && self.created_before.is_none_or(|before| record.created_at < before)
+            && self.tag.as_ref().is_none_or(|tag| record.tags.contains(tag))
+    }
+}
+
+/// Records folded from every block up to `height`, so later reads only
+/// need to scan the blocks produced since
+#[derive(Default)]
+struct ScanState {
+    height: Option<u32>,
+    /// Newest entry per key, tombstones included
+    records: HashMap<String, Record>,
+    /// Chunks found so far for keys whose newest record is split across
+    /// blobs that have not all been seen yet
+    partial: HashMap<String, Vec<Record>>,
+    /// Where the newest entry of each key in `records` was included; for a
+    /// chunked record, the location of its last chunk
+    locations: HashMap<String, BlobLocation>,
+}
+
+/// `ScanState` as persisted to `DatabaseClientConfig::state_file`. Values
+/// are stored as they are on-chain, so they stay encrypted when encryption
+/// is on.
+#[derive(Serialize, Deserialize)]
+struct StateFile {
+    /// The database the state belongs to; a file written for another app ID
+    /// or an older database under the same app ID is ignored
+    app_id: u32,
+    start_height: u64,
+    height: u32,
+    records: Vec<Record>,
+    partial: HashMap<String, Vec<Record>>,
+    locations: HashMap<String, BlobLocation>,
+}
+
+/// Contents of `DatabaseClientConfig::metadata_file`: the newest metadata
+/// this machine has seen for each app ID, with the block it was included in
+type MetadataFile = HashMap<u32, CachedMetadata>;
+
+#[derive(Serialize, Deserialize)]
+struct CachedMetadata {
+    metadata: DatabaseMetadata,
+    block_height: u32,
+    block_hash: avail_rust::prelude::H256,
+}
+
+/// How often `compact` checks whether its writes have become readable
+const READABLE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How many blocks after a write `poll_new_records` still recognizes a copy
+/// of it as a duplicate. Copies of one write land within a few blocks of
+/// each other, since they come from retries of the same submission.
+const DUPLICATE_WINDOW: u32 = 100;
+
+/// Position of a `DatabaseClient::poll_new_records` reader: the last block
+/// it has seen, the chunks of records not yet complete, and the entries
+/// reported within the last `DUPLICATE_WINDOW` blocks with their heights
+pub struct TailCursor {
+    height: u32,
+    partial: HashMap<ChunkGroup, Vec<Record>>,
+    reported: HashMap<EntryId, u32>,
+}
+
+impl TailCursor {
+    /// The last block height the cursor has seen
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+}
+
+/// Writes `value` as JSON to `path`. It goes to a temporary file first, so a
+/// crash never leaves a truncated file behind.
+fn write_json_atomically<T: Serialize>(path: &Path, value: &T) -> Result<(), DatabaseError> {
+    let tmp = path.with_extension("tmp");
+    let file = File::create(&tmp)
+        .map_err(|e| DatabaseError::IoError(format!("Failed to create '{}': {}", tmp.display(), e)))?;
+    serde_json::to_writer(BufWriter::new(file), value)
+        .map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+    std::fs::rename(&tmp, path)
+        .map_err(|e| DatabaseError::IoError(format!("Failed to write '{}': {}", path.display(), e)))
+}
+
+/// Reads the metadata file at `path`. A missing or unreadable file reads as
+/// empty, so the databases it would have listed are discovered instead.
+fn read_metadata_file(path: &Path) -> MetadataFile {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!("Ignoring unreadable metadata file '{}': {}", path.display(), e);
+            }
+            return MetadataFile::new();
+        }
+    };
+
+    serde_json::from_str(&contents).unwrap_or_else(|e| {
+        warn!("Ignoring unreadable metadata file '{}': {}", path.display(), e);
+        MetadataFile::new()
+    })
+}
+
+/// Orders records by `created_at` ascending, then by `key`
+fn sorted_records(map: HashMap<String, Record>) -> Vec<Record> {
+    let mut records: Vec<Record> = map.into_values().collect();
+    records.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.key.cmp(&b.key)));
+    records
+}
+
+/// Splits `value` into consecutive pieces whose JSON-escaped length is at
+/// most `budget` bytes each
+fn split_value(value: &str, budget: usize) -> Vec<String> {
+    let mut pieces = Vec::new();
+    let mut piece = String::new();
+    let mut piece_len = 0;
+
+    for c in value.chars() {
+        let escaped_len = serde_json::to_string(&c).map(|s| s.len() - 2).unwrap_or(6);
+        if piece_len + escaped_len > budget && !piece.is_empty() {
+            pieces.push(std::mem::take(&mut piece));
+            piece_len = 0;
+        }
+        piece.push(c);
+        piece_len += escaped_len;
+    }
+    if !piece.is_empty() {
+        pieces.push(piece);
+    }
+
+    pieces
+}
+
+/// Identifies the chunks of one write: an update keeps the record's `id`,
+/// so its `updated_at` tells its chunks apart from the previous version's
+type ChunkGroup = (String, Option<chrono::DateTime<chrono::Utc>>);
+
+fn chunk_group(record: &Record) -> ChunkGroup {
+    (record.id.clone(), record.updated_at)
+}
+
+/// Identifies one entry of one write: its chunk group and, for a chunk, its
+/// index. Every copy of a write has the same id, because a submission that
+/// is retried resends the same serialized record. When a retry follows an
+/// attempt that did land after all, e.g. after a timeout, the write appears
+/// twice on-chain; the id is how reads recognize the second copy.
+type EntryId = (ChunkGroup, Option<u32>);
+
+fn entry_id(record: &Record) -> EntryId {
+    (chunk_group(record), record.chunk_index)
+}
+
+/// Reassembles a record from its chunks. Returns `None` unless every chunk
+/// `0..chunk_total` is present.
+fn assemble_chunks(mut chunks: Vec<Record>) -> Option<Record> {
+    let total = chunks.first()?.chunk_total?;
+    chunks.sort_by_key(|chunk| chunk.chunk_index);
+    chunks.dedup_by_key(|chunk| chunk.chunk_index);
+
+    let complete = chunks.len() == total as usize
+        && chunks.iter().enumerate().all(|(i, chunk)| chunk.chunk_index == Some(i as u32));
+    if !complete {
+        return None;
+    }
+
+    let value = chunks.iter().map(|chunk| chunk.value.as_str()).collect();
+    let mut record = chunks.swap_remove(0);
+    record.value = value;
+    record.chunk_index = None;
+    record.chunk_total = None;
+
+    Some(record)
+}
+
+/// Applies `patch` to `target` as a JSON merge patch (RFC 7386): objects are
+/// merged key by key, recursively, a `null` in the patch removes the key,
+/// and any other value replaces what was there
+fn merge_json(target: &mut serde_json::Value, patch: serde_json::Value) {
+    let serde_json::Value::Object(patch) = patch else {
+        *target = patch;
+        return;
+    };
+    if !target.is_object() {
+        *target = serde_json::Value::Object(serde_json::Map::new());
+    }
+    let target = target.as_object_mut().expect("target was just made an object");
+
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(&key);
+        } else {
+            merge_json(target.entry(key).or_insert(serde_json::Value::Null), value);
+        }
+    }
+}
+
+/// Every record entry for `key` in the fetched blocks, newest-first, chunks
+/// and tombstones included. Duplicate copies of a write (see `EntryId`) are
+/// listed once, at the position of the newest copy.
+fn key_versions(blocks: &[BlockData], key: &str) -> Vec<Record> {
+    let mut seen = HashSet::new();
+    located_entries(blocks)
+        .filter_map(|(_, blob)| match Blob::parse(blob) {
+            Some(Blob::Record(record)) if record.key == key => Some(record),
+            _ => None,
+        })
+        .filter(|record| seen.insert(entry_id(record)))
+        .collect()
+}
+
+/// Keeps only the newest entry per key of a newest-first record stream such
+/// as `DatabaseClient::list_records_stream`, dropping deleted keys. Only the
+/// set of keys seen so far is held in memory. Keys whose newest entry is a
+/// chunk are dropped as well; read those with `get_record`.
+pub fn dedup_latest<S>(records: S) -> impl Stream<Item = Result<Record, DatabaseError>>
+where
+    S: Stream<Item = Result<Record, DatabaseError>>,
+{
+    let mut seen = HashSet::new();
+    records.try_filter_map(move |record| {
+        let newest = seen.insert(record.key.clone());
+        future::ready(Ok((newest && !record.deleted && !record.is_chunk()).then_some(record)))
+    })
+}
+
+/// Splits fetched blocks into individual JSON entries, each paired with
+/// where its blob was included, newest-first.
+///
+/// The order is that of `(block_height, tx_index, line)`, the position of an
+/// entry on-chain: extrinsics in a block are applied in index order, and a
+/// batch blob holds several newline-delimited entries written in order. The
+/// entries are sorted by that key rather than trusting the order the blocks
+/// and blobs were fetched in, so newest-wins never depends on it.
+fn located_entries(blocks: &[BlockData]) -> impl Iterator<Item = (BlobLocation, &str)> {
+    let mut entries: Vec<(BlobLocation, usize, &str)> = blocks
+        .iter()
+        .flat_map(|block| {
+            block.blobs.iter().flat_map(move |blob| {
+                let location = BlobLocation {
+                    block_height: block.height,
+                    block_hash: block.hash,
+                    tx_index: blob.tx_index,
+                };
+                blob.data.lines().enumerate().map(move |(line, entry)| (location, line, entry))
+            })
+        })
+        .collect();
+    entries.sort_by_key(|(location, line, _)| Reverse((location.block_height, location.tx_index, *line)));
+
+    entries.into_iter().map(|(location, _, entry)| (location, entry))
 }
 
-// Helper function to get current timestamp for logging
-fn log_with_timestamp(message: &str) {
-    let timestamp = chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f");
-    println!("[{}] {}", timestamp, message);
+/// Folds newest-first located entries into the newest entry per key and
+/// where it was included, tombstones included: the first entry seen for a
+/// key wins. Every chunk seen is also collected by the write it belongs to,
+/// since the newest entry of a chunked record is only one of its chunks.
+fn newest_per_key<'a>(
+    entries: impl Iterator<Item = (BlobLocation, &'a str)>,
+) -> (HashMap<String, (Record, BlobLocation)>, HashMap<ChunkGroup, Vec<Record>>) {
+    let mut newest: HashMap<String, (Record, BlobLocation)> = HashMap::new();
+    let mut chunks: HashMap<ChunkGroup, Vec<Record>> = HashMap::new();
+
+    for (location, entry) in entries {
+        if let Some(Blob::Record(record)) = Blob::parse(entry) {
+            if record.is_chunk() {
+                chunks.entry(chunk_group(&record)).or_default().push(record.clone());
+            }
+            newest.entry(record.key.clone()).or_insert((record, location));
+        }
+    }
+
+    (newest, chunks)
 }
 
 impl DatabaseClient {
     pub async fn new(
+        backend: Box<dyn AvailBackend>,
         app_id: u32,
         block_range: Option<u32>,
+    ) -> Result<Self, DatabaseError> {
+        let mut config = DatabaseClientConfig::new().app_id(app_id);
+        if let Some(block_range) = block_range {
+            config = config.block_range(block_range);
+        }
+
+        Self::with_backend(backend, config).await
+    }
+
+    /// Connects to the configured network and opens the database
+    pub async fn with_config(config: DatabaseClientConfig) -> Result<Self, DatabaseError> {
+        let conn = config.connect().await?;
+
+        Self::with_backend(Box::new(AvailRpcBackend::new(conn)), config).await
+    }
+
+    /// Opens the database on an existing backend. The backend is already
+    /// connected, so `config.network` and `config.retry_attempts` are not used.
+    /// Values are encrypted when the `ENCRYPTION_KEY` environment variable is set.
+    pub async fn with_backend(
+        backend: Box<dyn AvailBackend>,
+        config: DatabaseClientConfig,
     ) -> Result<Self, DatabaseError> {
         let mut db_client = Self {
-            app_id,
+            backend,
+            app_id: config.app_id,
             metadata: None,
-            block_range,
+            block_range: config.block_range,
+            state: Mutex::new(ScanState::default()),
+            cipher: ValueCipher::from_env()?,
+            max_blob_size: config.max_blob_size.unwrap_or(config.network.max_blob_size()),
+            strict: config.strict,
+            state_file: config.state_file,
+            metadata_file: config.metadata_file,
+            warn_on_overwrite: config.warn_on_overwrite,
+            read_only: config.read_only,
         };
 
-        if let Some(metadata) = db_client.discover_database().await? {
-            log_with_timestamp(&format!("Found existing database starting at block: {:?}", metadata.start_height));
+        let found = match db_client.load_metadata_file().await? {
+            Some(found) => Some(found),
+            None => db_client.discover_database().await?,
+        };
+        if let Some((metadata, included)) = found {
+            info!("Found existing database starting at block: {:?}", metadata.start_height);
+            db_client.save_metadata_file(&metadata, &included);
             db_client.metadata = Some(metadata);
         } else {
-            let latest_block_height = avail::get_latest_block_height_on_avail()
-                .await
-                .map_err(|e| DatabaseError::AvailError(e.to_string()))?;
+            let latest_block_height = db_client.backend.get_latest_block_height().await?;
 
             let metadata = DatabaseMetadata {
                 start_height: latest_block_height as u64,
                 record_count: 0,
-                last_updated: chrono::Utc::now()
+                last_updated: chrono::Utc::now(),
+                checkpoint_height: latest_block_height as u64,
+                snapshot_height: None,
+                key_filter: None,
             };
 
-            db_client.save_metadata(&metadata).await?;
+            if db_client.read_only {
+                warn!(
+                    "No database found and it can't be created read-only: opening an empty one at block {} that is not saved",
+                    latest_block_height
+                );
+            } else {
+                db_client.save_metadata(&metadata).await?;
+                info!("Created new database starting at block: {:?}", latest_block_height);
+            }
             db_client.metadata = Some(metadata);
+        }
+
+        match db_client.backend.get_account_balance().await {
+            Ok(Some(balance)) if balance < config.min_balance => warn!(
+                "The account balance is {}, which may not cover a submission: fund the account before writing",
+                avail::format_avail(balance)
+            ),
+            Ok(_) => {}
+            Err(e) => warn!("Could not check the account balance: {}", e),
+        }
 
-            log_with_timestamp(&format!("Created new database starting at block: {:?}", latest_block_height));
+        if let Some(state) = db_client.load_state_file()? {
+            let latest_block_height = db_client.backend.get_latest_block_height().await?;
+            if state.height.is_some_and(|height| height <= latest_block_height) {
+                *db_client.state.lock().unwrap() = state;
+            } else {
+                warn!("Ignoring state file: it is ahead of the latest readable block {}", latest_block_height);
+            }
         }
 
         Ok(db_client)
     }
 
-    async fn discover_database(&self) -> Result<Option<DatabaseMetadata>, DatabaseError> {
-        let latest_block_height = avail::get_latest_block_height_on_avail()
-            .await
-            .map_err(|e| DatabaseError::AvailError(e.to_string()))?;
+    /// Reads the scan state saved by a previous session. Returns `None` when
+    /// no state file is configured or it does not exist yet, and when it
+    /// can't be used: unreadable, or written for a different database.
+    fn load_state_file(&self) -> Result<Option<ScanState>, DatabaseError> {
+        let Some(path) = &self.state_file else {
+            return Ok(None);
+        };
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(DatabaseError::IoError(format!("Failed to read '{}': {}", path.display(), e)));
+            }
+        };
 
-        let start_height = if let Some(block_range) = self.block_range {
-            latest_block_height.saturating_sub(block_range)
-        } else {
-            latest_block_height.saturating_sub(10)
+        let saved: StateFile = match serde_json::from_str(&contents) {
+            Ok(saved) => saved,
+            Err(e) => {
+                warn!("Ignoring unreadable state file '{}': {}", path.display(), e);
+                return Ok(None);
+            }
+        };
+        let start_height = self.metadata.as_ref().map(|m| m.start_height).unwrap_or_default();
+        if saved.app_id != self.app_id || saved.start_height != start_height {
+            warn!(
+                "Ignoring state file '{}': written for app ID {} starting at block {}, not app ID {} starting at block {}",
+                path.display(), saved.app_id, saved.start_height, self.app_id, start_height
+            );
+            return Ok(None);
+        }
+
+        let mut records = HashMap::new();
+        for record in saved.records {
+            records.insert(record.key.clone(), self.open(record)?);
+        }
+        info!(
+            "Resuming from state file '{}': {} keys scanned up to block {}",
+            path.display(), records.len(), saved.height
+        );
+
+        Ok(Some(ScanState {
+            height: Some(saved.height),
+            records,
+            partial: saved.partial,
+            locations: saved.locations,
+        }))
+    }
+
+    /// Persists the scan state to the state file, if one is configured.
+    /// Failures are logged rather than returned: the state can always be
+    /// rebuilt from the chain.
+    fn save_state_file(&self, state: &ScanState) {
+        let (Some(path), Some(height)) = (&self.state_file, state.height) else {
+            return;
+        };
+
+        let saved = state
+            .records
+            .values()
+            .map(|record| self.seal(record.clone()))
+            .collect::<Result<Vec<_>, _>>()
+            .map(|records| StateFile {
+                app_id: self.app_id,
+                start_height: self.start_height() as u64,
+                height,
+                records,
+                partial: state.partial.clone(),
+                locations: state.locations.clone(),
+            });
+        let result = saved.and_then(|saved| write_json_atomically(path, &saved));
+
+        if let Err(e) = result {
+            warn!("Failed to save state file '{}': {}", path.display(), e);
+        }
+    }
+
+    /// Reads this database's metadata from the metadata file, if one is
+    /// configured and holds an entry for the app ID, together with the block
+    /// it was included in.
+    ///
+    /// The entry is only trusted if that block is still on-chain with the
+    /// same hash and still holds a metadata blob for the same database;
+    /// otherwise `None` is returned and the caller falls back to discovery.
+    /// Metadata written by other clients since is not seen, which is safe:
+    /// an older snapshot, key filter or start height only makes reads scan
+    /// more blocks.
+    async fn load_metadata_file(&self) -> Result<Option<(DatabaseMetadata, BlobLocation)>, DatabaseError> {
+        let Some(path) = &self.metadata_file else {
+            return Ok(None);
+        };
+        let Some(cached) = read_metadata_file(path).remove(&self.app_id) else {
+            return Ok(None);
+        };
+
+        let latest_block_height = self.backend.get_latest_block_height().await?;
+        if cached.block_height > latest_block_height {
+            warn!(
+                "Ignoring metadata file '{}': block {} is ahead of the latest readable block {}",
+                path.display(), cached.block_height, latest_block_height
+            );
+            return Ok(None);
+        }
+
+        let blocks = self
+            .backend
+            .get_blocks_by_range(self.app_id, cached.block_height, cached.block_height)
+            .await?;
+        let still_there = located_entries(&blocks)
+            .filter(|(location, _)| location.block_hash == cached.block_hash)
+            .find_map(|(location, line)| match Blob::parse(line) {
+                Some(Blob::Metadata(metadata)) if metadata.start_height == cached.metadata.start_height => {
+                    Some(location)
+                }
+                _ => None,
+            });
+        let Some(location) = still_there else {
+            warn!(
+                "Ignoring metadata file '{}': block {} no longer holds the database's metadata",
+                path.display(), cached.block_height
+            );
+            return Ok(None);
+        };
+
+        info!(
+            "Loaded metadata from '{}' (block {}), skipping discovery",
+            path.display(), cached.block_height
+        );
+        Ok(Some((cached.metadata, location)))
+    }
+
+    /// Records `metadata` as the newest known for this app ID in the metadata
+    /// file, if one is configured. Entries for other app IDs are kept.
+    /// Failures are logged rather than returned: the metadata can always be
+    /// rediscovered from the chain.
+    fn save_metadata_file(&self, metadata: &DatabaseMetadata, included: &BlobLocation) {
+        let Some(path) = &self.metadata_file else {
+            return;
         };
 
-        log_with_timestamp(&format!(
+        let mut saved = read_metadata_file(path);
+        saved.insert(
+            self.app_id,
+            CachedMetadata {
+                metadata: metadata.clone(),
+                block_height: included.block_height,
+                block_hash: included.block_hash,
+            },
+        );
+        if let Err(e) = write_json_atomically(path, &saved) {
+            warn!("Failed to save metadata file '{}': {}", path.display(), e);
+        }
+    }
+
+    async fn discover_database(&self) -> Result<Option<(DatabaseMetadata, BlobLocation)>, DatabaseError> {
+        let latest_block_height = self.backend.get_latest_block_height().await?;
+
+        let start_height = latest_block_height.saturating_sub(self.block_range);
+
+        info!(
             "Searching for existing database (blocks {}..{})",
             start_height,
             latest_block_height
-        ));
+        );
 
-        let data = avail::get_data_from_avail_by_app_id(
-                self.app_id,
-                latest_block_height - start_height
-            ).await
-            .map_err(|e| DatabaseError::AvailError(e.to_string()))?;
+        let blocks = self.backend
+            .get_blocks_by_range(self.app_id, start_height, latest_block_height)
+            .await?;
 
-        for line in data.iter().rev() {
-            if let Ok(metadata) = serde_json::from_str::<DatabaseMetadata>(line) {
-                log_with_timestamp(&format!("Found existing database at height {}", metadata.start_height));
-                return Ok(Some(metadata));
+        // Newest-first, so the latest metadata version (with the latest
+        // snapshot height) wins
+        for (location, line) in located_entries(&blocks) {
+            if let Some(Blob::Metadata(metadata)) = Blob::parse(line) {
+                info!("Found existing database at height {}", metadata.start_height);
+                return Ok(Some((metadata, location)));
             }
         }
 
-        log_with_timestamp(&format!("No existing database found, creating new one at height {}", latest_block_height));
+        info!("No existing database found, creating new one at height {}", latest_block_height);
         Ok(None)
     }
 
+    /// Submits a blob under the database's app ID, rejecting blobs over
+    /// `max_blob_size` before they reach the network. The size is checked
+    /// before compression, so a blob under the limit always fits. Every
+    /// write goes through here, so this is also where read-only mode
+    /// refuses them.
+    async fn submit(&self, data: String) -> Result<SubmissionReceipt, DatabaseError> {
+        if self.read_only {
+            return Err(DatabaseError::ReadOnly);
+        }
+        if data.len() > self.max_blob_size {
+            return Err(DatabaseError::BlobTooLarge { size: data.len(), max: self.max_blob_size });
+        }
+
+        self.backend.submit_data(self.app_id, data).await
+    }
+
     async fn save_metadata(&self, metadata: &DatabaseMetadata) -> Result<(), DatabaseError> {
-        let json = serde_json::to_string(metadata)
-            .map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+        let json = Blob::Metadata(metadata.clone()).to_json()?;
 
-        avail::submit_data_to_avail_by_app_id(self.app_id, json)
-            .await
-            .map_err(|e| DatabaseError::AvailError(e.to_string()))?;
+        let receipt = self.submit(json).await?;
+        // A dry run reports a zero block hash: nothing was included, so the
+        // file keeps the last metadata that really was
+        if receipt.block_hash.is_zero() {
+            return Ok(());
+        }
+        // The receipt has no extrinsic index, but the metadata file only
+        // needs the block
+        let included = BlobLocation {
+            block_height: receipt.block_height,
+            block_hash: receipt.block_hash,
+            tx_index: 0,
+        };
+        self.save_metadata_file(metadata, &included);
 
         Ok(())
     }
 
-    pub async fn add_record(&mut self, record: Record) -> Result<(), DatabaseError> {
-        let json = serde_json::to_string(&record)
-            .map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+    /// Applies `update` to the current metadata, advances its checkpoint to
+    /// the block the write landed in and writes the new version on-chain
+    async fn update_metadata(
+        &mut self,
+        receipt: &SubmissionReceipt,
+        update: impl FnOnce(&mut DatabaseMetadata),
+    ) -> Result<(), DatabaseError> {
+        let Some(mut metadata) = self.metadata.clone() else {
+            return Ok(());
+        };
 
-        avail::submit_data_to_avail_by_app_id(self.app_id, json)
-            .await
-            .map_err(|e| DatabaseError::AvailError(e.to_string()))?;
+        update(&mut metadata);
+        metadata.last_updated = chrono::Utc::now();
+        if let Some(key_filter) = self.current_key_filter() {
+            metadata.key_filter = Some(key_filter);
+        }
+        metadata.checkpoint_height = metadata.checkpoint_height.max(receipt.block_height as u64);
+
+        self.save_metadata(&metadata).await?;
+        self.metadata = Some(metadata);
+
+        Ok(())
+    }
+
+    /// A key filter over the cached scan state, if it is warm. The state is a
+    /// full fold of the blocks up to its height, so the filter holds every key
+    /// with an entry there; newer writes are not included and lookups scan
+    /// the blocks after the filter's height instead.
+    fn current_key_filter(&self) -> Option<KeyFilter> {
+        let state = self.state.lock().unwrap();
+        let height = state.height?;
+
+        let mut keys: HashSet<&str> = state.records.keys().map(String::as_str).collect();
+        keys.extend(state.partial.keys().map(String::as_str));
+
+        Some(KeyFilter {
+            height: height as u64,
+            filter: BloomFilter::for_keys(keys.into_iter()),
+        })
+    }
+
+    /// Whether the metadata's key filter proves `key` has no record, which
+    /// spares a cold lookup of a missing key the full scan: only the blocks
+    /// after the filter's height are searched for the key. Returns `false`
+    /// whenever that can't be shown, including when the scan state is
+    /// already warm and a regular read is cheap.
+    async fn definitely_absent(&self, key: &str) -> Result<bool, DatabaseError> {
+        if self.state.lock().unwrap().height.is_some() {
+            return Ok(false);
+        }
+        let Some(key_filter) = self.metadata.as_ref().and_then(|m| m.key_filter.as_ref()) else {
+            return Ok(false);
+        };
+        if key_filter.filter.contains(key) {
+            return Ok(false);
+        }
+
+        let latest_block_height = self.backend.get_latest_block_height().await?;
+        let from_height = (key_filter.height as u32 + 1).max(self.start_height());
+        if from_height <= latest_block_height {
+            info!(
+                "Key '{}' is not in the key filter, scanning blocks {}..={} only",
+                key, from_height, latest_block_height
+            );
+            let blocks = self.backend
+                .get_blocks_by_range(self.app_id, from_height, latest_block_height)
+                .await?;
+
+            let mentioned = located_entries(&blocks)
+                .any(|(_, entry)| matches!(Blob::parse(entry), Some(Blob::Record(record)) if record.key == key));
+            if mentioned {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Encrypts the value of a record about to be submitted. Tombstones have
+    /// no value and are left as is.
+    fn seal(&self, mut record: Record) -> Result<Record, DatabaseError> {
+        if let Some(cipher) = &self.cipher {
+            if !record.deleted {
+                record.value = cipher.encrypt(&record.value)?;
+            }
+        }
+
+        Ok(record)
+    }
+
+    /// Decrypts the value of a record read from a blob. Without a key,
+    /// encrypted values are returned as stored.
+    fn open(&self, mut record: Record) -> Result<Record, DatabaseError> {
+        if let Some(cipher) = &self.cipher {
+            record.value = cipher.decrypt(&record.value)?;
+        }
+
+        Ok(record)
+    }
+
+    /// Encrypts and submits a record. A record too large for one blob is
+    /// split into chunk blobs sharing its `id`, submitted in order; the
+    /// receipt of the last chunk is returned.
+    async fn submit_record(&self, record: Record) -> Result<SubmissionReceipt, DatabaseError> {
+        let record = self.seal(record)?;
 
-        if let Some(mut metadata) = self.metadata.clone() {
-            metadata.record_count += 1;
-            metadata.last_updated = chrono::Utc::now();
-            self.save_metadata(&metadata).await?;
-            self.metadata = Some(metadata);
+        let json = Blob::Record(record.clone()).to_json()?;
+        if json.len() <= self.max_blob_size {
+            return self.submit(json).await;
         }
 
+        // Room left for the value once the rest of a chunk blob is serialized
+        let overhead = Blob::Record(Record {
+            value: String::new(),
+            chunk_index: Some(u32::MAX),
+            chunk_total: Some(u32::MAX),
+            ..record.clone()
+        }).to_json()?.len();
+        let budget = self.max_blob_size.saturating_sub(overhead);
+        if budget == 0 {
+            return Err(DatabaseError::BlobTooLarge { size: json.len(), max: self.max_blob_size });
+        }
+
+        let pieces = split_value(&record.value, budget);
+        let total = pieces.len() as u32;
+        info!("Splitting record '{}' into {} chunks", record.key, total);
+
+        let mut receipt = None;
+        for (index, piece) in pieces.into_iter().enumerate() {
+            let chunk = Record {
+                value: piece,
+                chunk_index: Some(index as u32),
+                chunk_total: Some(total),
+                ..record.clone()
+            };
+            receipt = Some(self.submit(Blob::Record(chunk).to_json()?).await?);
+        }
+
+        Ok(receipt.expect("a record too large for one blob has at least one chunk"))
+    }
+
+    /// Counts the entries of `blocks` that parse as no known blob type and
+    /// warns about them; they usually mean another application shares the
+    /// app ID. In strict mode they fail the read instead.
+    fn check_unrecognized(&self, blocks: &[BlockData]) -> Result<(), DatabaseError> {
+        let count = located_entries(blocks)
+            .filter(|(_, entry)| Blob::parse(entry).is_none())
+            .count();
+        if count == 0 {
+            return Ok(());
+        }
+        if self.strict {
+            return Err(DatabaseError::UnrecognizedBlobs(count));
+        }
+
+        warn!("Skipped {} unrecognized blobs", count);
         Ok(())
     }
 
-    pub async fn get_record(&self, key: &str) -> Result<Option<Record>, DatabaseError> {
-        let latest_block_height = avail::get_latest_block_height_on_avail()
-            .await
-            .map_err(|e| DatabaseError::AvailError(e.to_string()))?;
-        let db_start = self.metadata.as_ref().map(|m| m.start_height).unwrap_or(0);
-        log_with_timestamp(&format!(
-            "Searching for record with key '{}' (database start: {}, current height: {})",
-            key, db_start, latest_block_height
-        ));
-        let block_range_to_search = if latest_block_height as u64 >= db_start {
-            (latest_block_height as u64 - db_start) as u32
-        } else {
-            0
+    /// First block height of the database
+    fn start_height(&self) -> u32 {
+        self.metadata.as_ref().map(|m| m.start_height as u32).unwrap_or(0)
+    }
+
+    /// Adds a record and returns where its blob was included on-chain.
+    /// Adding an existing key replaces its value; with
+    /// `DatabaseClientConfig::warn_on_overwrite`, that is logged as a warning.
+    ///
+    /// Exactly-once is not guaranteed: a write that failed, e.g. by timing
+    /// out, may still have landed. To retry it, pass a clone of the same
+    /// `Record` rather than a new one, so both copies share an `id` and reads
+    /// treat them as one write. The metadata's `record_count` may still be
+    /// off by one after such a retry.
+    pub async fn add_record(&mut self, record: Record) -> Result<SubmissionReceipt, DatabaseError> {
+        validate_key(&record.key)?;
+        if self.warn_on_overwrite && self.key_exists(&record.key).await? {
+            warn!("Overwriting existing key '{}'", record.key);
+        }
+
+        let receipt = self.submit_record(record).await?;
+
+        self.update_metadata(&receipt, |metadata| metadata.record_count += 1).await?;
+
+        Ok(receipt)
+    }
+
+    /// Writes `key` only if it has no live record, returning whether it wrote.
+    ///
+    /// Best effort: Avail has no atomic compare-and-swap, so another writer
+    /// can add the key between the `get_record` check and the submission
+    /// landing in a block. Both writes then succeed and the newer one wins.
+    pub async fn put_if_absent(&mut self, key: &str, value: String) -> Result<bool, DatabaseError> {
+        if self.get_record(key).await?.is_some() {
+            return Ok(false);
+        }
+
+        self.add_record(Record::new(key.to_string(), value)).await?;
+
+        Ok(true)
+    }
+
+    /// Adds `delta` to the integer stored under `key` and returns the new
+    /// value. A missing key counts as 0.
+    ///
+    /// This is a read-modify-write, not an atomic increment: without on-chain
+    /// compare-and-swap, concurrent writers incrementing the same key can
+    /// lose each other's updates.
+    pub async fn increment(&mut self, key: &str, delta: i64) -> Result<i64, DatabaseError> {
+        let current = self.get_record(key).await?;
+
+        let value = match &current {
+            Some(record) => record.value.trim().parse::<i64>().map_err(|_| {
+                DatabaseError::InvalidInput(format!(
+                    "Value of '{}' is not an integer: '{}'",
+                    key, record.value
+                ))
+            })?,
+            None => 0,
         };
-        let blobs = avail::get_data_from_avail_by_app_id(
-            self.app_id,
-            block_range_to_search
-        ).await
-        .map_err(|e| DatabaseError::AvailError(e.to_string()))?;
+        let value = value.checked_add(delta).ok_or_else(|| {
+            DatabaseError::InvalidInput(format!("Incrementing '{}' by {} overflows", key, delta))
+        })?;
 
-        for blob in blobs.iter().rev() {
-            if serde_json::from_str::<crate::schema::DatabaseMetadata>(blob).is_ok() {
-                continue;
+        match current {
+            Some(_) => {
+                self.update_record(key, value.to_string()).await?;
             }
-            if let Ok(record) = serde_json::from_str::<Record>(blob) {
-                if record.key == key {
-                    log_with_timestamp(&format!("Found record with key '{}' at height {}", key, latest_block_height));
-                    return Ok(Some(record));
-                }
+            None => {
+                self.add_record(Record::new(key.to_string(), value.to_string())).await?;
             }
         }
 
-        Ok(None)
+        Ok(value)
     }
 
-    pub async fn list_records(&self) -> Result<Vec<Record>, DatabaseError> {
-        let latest_block_height = avail::get_latest_block_height_on_avail()
-            .await
-            .map_err(|e| DatabaseError::AvailError(e.to_string()))?;
-        let db_start = self.metadata.as_ref().map(|m| m.start_height).unwrap_or(0);
-        log_with_timestamp(&format!(
-            "Listing all records (database start: {}, current height: {})",
-            db_start, latest_block_height
-        ));
-
-        let block_range_to_search = if latest_block_height as u64 >= db_start {
-            (latest_block_height as u64 - db_start) as u32
-        } else {
-            0
+    /// Adds many records with a single Avail submission: the records are
+    /// serialized into one newline-delimited blob, followed by one metadata write
+    /// Returns `None` without submitting anything when `records` is empty.
+    /// With `DatabaseClientConfig::warn_on_overwrite`, every key that already
+    /// exists is logged as a warning, found by one read for the whole batch.
+    pub async fn add_records(
+        &mut self,
+        records: Vec<Record>,
+    ) -> Result<Option<SubmissionReceipt>, DatabaseError> {
+        if records.is_empty() {
+            return Ok(None);
+        }
+        for record in &records {
+            validate_key(&record.key)?;
+        }
+        if self.warn_on_overwrite {
+            let live = self.latest_records().await?;
+            for record in records.iter().filter(|record| live.contains_key(&record.key)) {
+                warn!("Overwriting existing key '{}'", record.key);
+            }
+        }
+
+        let lines = records
+            .iter()
+            .map(|record| Blob::Record(self.seal(record.clone())?).to_json())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let receipt = self.submit(lines.join("\n")).await?;
+
+        self.update_metadata(&receipt, |metadata| metadata.record_count += records.len() as u64).await?;
+
+        Ok(Some(receipt))
+    }
+
+    /// Updates the value of an existing record, preserving its `id` and
+    /// `created_at`, setting `updated_at` to now and incrementing its `version`
+    pub async fn update_record(&mut self, key: &str, value: String) -> Result<Record, DatabaseError> {
+        validate_key(key)?;
+
+        let Some(mut record) = self.get_record(key).await? else {
+            return Err(DatabaseError::NotFound(key.to_string()));
         };
-        let blobs = avail::get_data_from_avail_by_app_id(
-            self.app_id,
-            block_range_to_search
-        ).await
-        .map_err(|e| DatabaseError::AvailError(e.to_string()))?;
-        
-        let mut map: HashMap<String, Record> = HashMap::new();
-
-        for blob in blobs.iter().rev() {
-            if serde_json::from_str::<crate::schema::DatabaseMetadata>(blob).is_ok() {
-                continue;
+
+        record.value = value;
+        record.updated_at = Some(chrono::Utc::now());
+        record.version += 1;
+
+        let receipt = self.submit_record(record.clone()).await?;
+
+        self.update_metadata(&receipt, |_| {}).await?;
+
+        Ok(record)
+    }
+
+    /// Updates `key` like `update_record`, but only if its current `version`
+    /// is `expected_version`; otherwise fails with `DatabaseError::Conflict`
+    /// and writes nothing. Returns the record as written, at
+    /// `expected_version + 1`.
+    ///
+    /// Best effort, like `put_if_absent`: Avail has no atomic
+    /// compare-and-swap, so a writer whose update lands between this read
+    /// and this write is not detected, and both updates carry the same
+    /// version. It does catch every update seen by the time of the read,
+    /// which is most conflicts between writers working at human speed.
+    /// A key that is deleted and added again starts over at version 1.
+    pub async fn compare_and_swap(
+        &mut self,
+        key: &str,
+        expected_version: u64,
+        new_value: String,
+    ) -> Result<Record, DatabaseError> {
+        validate_key(key)?;
+
+        let Some(mut record) = self.get_record(key).await? else {
+            return Err(DatabaseError::NotFound(key.to_string()));
+        };
+        if record.version != expected_version {
+            return Err(DatabaseError::Conflict {
+                key: key.to_string(),
+                expected: expected_version,
+                found: record.version,
+            });
+        }
+
+        record.value = new_value;
+        record.updated_at = Some(chrono::Utc::now());
+        record.version += 1;
+
+        let receipt = self.submit_record(record.clone()).await?;
+
+        self.update_metadata(&receipt, |_| {}).await?;
+
+        Ok(record)
+    }
+
+    /// Patches the JSON value of an existing record with a JSON merge patch
+    /// (see `merge_json`) and writes the result like `update_record`, keeping
+    /// the record's `id` and `created_at` and setting `updated_at` to now.
+    /// Fails with `DatabaseError::InvalidInput` if the stored value or the
+    /// patch is not valid JSON.
+    pub async fn merge_record(&mut self, key: &str, patch: &str) -> Result<Record, DatabaseError> {
+        validate_key(key)?;
+
+        let Some(mut record) = self.get_record(key).await? else {
+            return Err(DatabaseError::NotFound(key.to_string()));
+        };
+
+        let mut value: serde_json::Value = serde_json::from_str(&record.value).map_err(|e| {
+            DatabaseError::InvalidInput(format!("Value of '{}' is not valid JSON: {}", key, e))
+        })?;
+        let patch: serde_json::Value = serde_json::from_str(patch)
+            .map_err(|e| DatabaseError::InvalidInput(format!("Patch is not valid JSON: {}", e)))?;
+        merge_json(&mut value, patch);
+
+        record.value = value.to_string();
+        record.updated_at = Some(chrono::Utc::now());
+        record.version += 1;
+
+        let receipt = self.submit_record(record.clone()).await?;
+
+        self.update_metadata(&receipt, |_| {}).await?;
+
+        Ok(record)
+    }
+
+    /// Deletes a record by writing a tombstone blob for its key.
+    /// Blobs on Avail are append-only, so the deletion is logical: reads treat
+    /// the most recent tombstone for a key as "not present".
+    /// Returns `DatabaseError::NotFound` if the key has no live record.
+    pub async fn delete_record(&mut self, key: &str) -> Result<SubmissionReceipt, DatabaseError> {
+        validate_key(key)?;
+        if self.get_record(key).await?.is_none() {
+            return Err(DatabaseError::NotFound(key.to_string()));
+        }
+
+        let tombstone = Record::tombstone(key.to_string());
+        let json = Blob::Record(tombstone).to_json()?;
+
+        let receipt = self.submit(json).await?;
+
+        self.update_metadata(&receipt, |metadata| {
+            metadata.record_count = metadata.record_count.saturating_sub(1)
+        }).await?;
+
+        Ok(receipt)
+    }
+
+    /// Deletes every record by writing a tombstone for each live key,
+    /// including keys whose chunks are still incomplete. The tombstones are
+    /// packed newline-delimited into as few blobs as `max_blob_size` allows.
+    /// Like `delete_record` the deletion is logical: the older entries stay
+    /// on-chain and reads ignore them because a newer tombstone exists.
+    /// Returns how many keys were cleared and the receipt of the last blob,
+    /// or `None` when there was nothing to clear.
+    pub async fn clear(&mut self) -> Result<(usize, Option<SubmissionReceipt>), DatabaseError> {
+        let mut keys: Vec<String> = self.latest_records().await?.into_keys().collect();
+        keys.extend(self.state.lock().unwrap().partial.keys().cloned());
+        keys.sort();
+        keys.dedup();
+
+        let lines = keys
+            .iter()
+            .map(|key| Blob::Record(Record::tombstone(key.clone())).to_json())
+            .collect::<Result<Vec<_>, _>>()?;
+        let blobs = self.pack_entries(lines);
+
+        let mut receipt = None;
+        for (i, blob) in blobs.into_iter().enumerate() {
+            info!("Submitting tombstone blob {} ({} bytes)", i + 1, blob.len());
+            receipt = Some(self.submit(blob).await?);
+        }
+        if let Some(receipt) = &receipt {
+            self.update_metadata(receipt, |metadata| metadata.record_count = 0).await?;
+        }
+
+        Ok((keys.len(), receipt))
+    }
+
+    /// Packs entries newline-delimited, in order, into as few blobs of at
+    /// most `max_blob_size` bytes as possible
+    fn pack_entries(&self, entries: impl IntoIterator<Item = String>) -> Vec<String> {
+        let mut blobs: Vec<String> = Vec::new();
+        for line in entries {
+            match blobs.last_mut() {
+                Some(blob) if blob.len() + 1 + line.len() <= self.max_blob_size => {
+                    blob.push('\n');
+                    blob.push_str(&line);
+                }
+                _ => blobs.push(line),
             }
-            if let Ok(record) = serde_json::from_str::<Record>(blob) {
-                map.entry(record.key.clone()).or_insert(record);
+        }
+
+        blobs
+    }
+
+    /// Rewrites every live record as fresh blobs and moves the database's
+    /// `start_height` to the first of them, so later scans skip the
+    /// overwritten versions and tombstones written so far. This only saves
+    /// scan time: blobs are append-only, so the old entries stay on-chain,
+    /// and a reader that ignores the metadata can still find them.
+    ///
+    /// Records keep their `id` and timestamps, and keys whose chunks are
+    /// still incomplete are copied chunk by chunk so their remaining chunks
+    /// complete them. Other clients may write while the copies are
+    /// submitted; their entries would either fall before the new start or be
+    /// outranked by an older value's copy, so every entry found between the
+    /// scanned state and the last copy is written again after it, in its
+    /// original order, until a round of submissions sees no new ones. A
+    /// client that opened the database earlier still holds the old start
+    /// height, and its next metadata write moves the start back; reads stay
+    /// correct, only as slow as before.
+    ///
+    /// Returns how many records were rewritten and the new start height.
+    pub async fn compact(&mut self) -> Result<(usize, u32), DatabaseError> {
+        let records = self.latest_records().await?;
+        let (scanned_height, partial) = {
+            let state = self.state.lock().unwrap();
+            (state.height.unwrap_or(self.start_height()), state.partial.clone())
+        };
+        let count = records.len();
+
+        // Entries written by this compaction, told apart from other clients'
+        // writes of the same key by their chunk group
+        let mut copied: HashSet<(String, ChunkGroup)> = HashSet::new();
+        let mut lines = Vec::new();
+        let mut oversized = Vec::new();
+        for record in sorted_records(records) {
+            copied.insert((record.key.clone(), chunk_group(&record)));
+            let line = Blob::Record(self.seal(record.clone())?).to_json()?;
+            if line.len() <= self.max_blob_size {
+                lines.push(line);
+            } else {
+                oversized.push(record);
             }
         }
-        log_with_timestamp(&format!("Found {} records", map.len()));
+        for chunk in partial.into_values().flatten() {
+            copied.insert((chunk.key.clone(), chunk_group(&chunk)));
+            lines.push(Blob::Record(chunk).to_json()?);
+        }
+
+        info!("Compacting {} records written up to block {}", count, scanned_height);
+        let mut receipts = Vec::new();
+        for blob in self.pack_entries(lines) {
+            receipts.push(self.submit(blob).await?);
+        }
+        for record in oversized {
+            receipts.push(self.submit_record(record).await?);
+        }
+        let new_start = receipts.first().map(|r| r.block_height).unwrap_or(scanned_height + 1);
+
+        let mut checked_height = scanned_height;
+        let mut last_height = receipts.last().map(|r| r.block_height).unwrap_or(scanned_height);
+        while last_height > checked_height {
+            self.wait_until_readable(last_height).await?;
+            let blocks = self.backend
+                .get_blocks_by_range(self.app_id, checked_height + 1, last_height)
+                .await?;
+            checked_height = last_height;
+
+            let mut carried = Vec::new();
+            let mut carried_groups = Vec::new();
+            for (_, entry) in located_entries(&blocks).collect::<Vec<_>>().into_iter().rev() {
+                let Some(Blob::Record(record)) = Blob::parse(entry) else {
+                    continue;
+                };
+                let group = (record.key.clone(), chunk_group(&record));
+                if !copied.contains(&group) {
+                    carried.push(entry.to_string());
+                    carried_groups.push(group);
+                }
+            }
+            if carried.is_empty() {
+                break;
+            }
+            copied.extend(carried_groups);
+
+            info!("Writing {} entries other clients wrote during compaction again", carried.len());
+            for blob in self.pack_entries(carried) {
+                last_height = self.submit(blob).await?.block_height;
+            }
+        }
+
+        // The snapshot and the cached state describe the old range. The key
+        // filter stays valid: the live keys are a subset of the keys it holds.
+        let Some(mut metadata) = self.metadata.clone() else {
+            return Err(DatabaseError::NotFound("database metadata".to_string()));
+        };
+        metadata.start_height = new_start as u64;
+        metadata.record_count = count as u64;
+        metadata.snapshot_height = None;
+        metadata.last_updated = chrono::Utc::now();
+        metadata.checkpoint_height = metadata.checkpoint_height.max(last_height as u64);
+
+        self.save_metadata(&metadata).await?;
+        self.metadata = Some(metadata);
+        *self.state.lock().unwrap() = ScanState::default();
+        info!("Compacted {} records, the database now starts at block {}", count, new_start);
+
+        Ok((count, new_start))
+    }
+
+    /// Waits until reads reach `height`, e.g. until a block just written to
+    /// is finalized
+    async fn wait_until_readable(&self, height: u32) -> Result<(), DatabaseError> {
+        loop {
+            let latest_block_height = self.backend.get_latest_block_height().await?;
+            if latest_block_height >= height {
+                return Ok(());
+            }
+
+            info!("Waiting for block {} to become readable (at {})", height, latest_block_height);
+            tokio::time::sleep(READABLE_POLL_INTERVAL).await;
+        }
+    }
+
+    pub async fn get_record(&self, key: &str) -> Result<Option<Record>, DatabaseError> {
+        self.get_record_with(key, ReadConsistency::Strong).await
+    }
+
+    /// `get_record` at the given read consistency
+    pub async fn get_record_with(
+        &self,
+        key: &str,
+        consistency: ReadConsistency,
+    ) -> Result<Option<Record>, DatabaseError> {
+        info!("Searching for record with key '{}'", key);
+
+        if consistency == ReadConsistency::Strong && self.definitely_absent(key).await? {
+            info!("No record with key '{}'", key);
+            return Ok(None);
+        }
+
+        let mut records = self.records_with(consistency).await?;
+        let record = records.remove(key);
+        if record.is_some() {
+            info!("Found record with key '{}'", key);
+        } else if let Some(chunks) = self.state.lock().unwrap().partial.get(key) {
+            return Err(DatabaseError::MissingChunks {
+                key: key.to_string(),
+                found: chunks.len(),
+                total: chunks[0].chunk_total.unwrap_or_default(),
+            });
+        }
+
+        Ok(record)
+    }
+
+    /// Like `get_record`, but also returns where the record was included
+    /// on-chain (for a chunked record, its last chunk)
+    pub async fn get_record_located(&self, key: &str) -> Result<Option<(Record, BlobLocation)>, DatabaseError> {
+        let Some(record) = self.get_record(key).await? else {
+            return Ok(None);
+        };
+        let location = self.state.lock().unwrap().locations.get(key).copied();
+
+        Ok(location.map(|location| (record, location)))
+    }
+
+    /// Like `get_record`, but also returns the timestamp of the block the
+    /// record was included in. Unlike `created_at`, which the writing client
+    /// sets and could get wrong, the block time comes from the chain.
+    pub async fn get_record_with_block_time(
+        &self,
+        key: &str,
+    ) -> Result<Option<(Record, chrono::DateTime<chrono::Utc>)>, DatabaseError> {
+        let Some((record, location)) = self.get_record_located(key).await? else {
+            return Ok(None);
+        };
+        let block_time = self.backend.get_block_timestamp(location.block_hash).await?;
+
+        Ok(Some((record, block_time)))
+    }
+
+    /// Like `get_record`, but also returns an `InclusionProof`: the block
+    /// hash, height and extrinsic index of the blob the record was read from,
+    /// plus the raw entry, so a caller can check it against the chain
+    /// independently. For a chunked record the proof covers its last chunk.
+    pub async fn get_with_proof(&self, key: &str) -> Result<Option<(Record, InclusionProof)>, DatabaseError> {
+        let Some((record, location)) = self.get_record_located(key).await? else {
+            return Ok(None);
+        };
+
+        let entry = self.find_entry(&location, |candidate| {
+            candidate.key == record.key && candidate.id == record.id && candidate.updated_at == record.updated_at
+        }).await?;
+        let Some(entry) = entry else {
+            return Err(DatabaseError::NotFound(format!(
+                "entry for '{}' in extrinsic {} of block {}",
+                key, location.tx_index, location.block_height
+            )));
+        };
+
+        Ok(Some((record, InclusionProof { location, entry })))
+    }
+
+    /// Checks an `InclusionProof` against the chain: the block at the proof's
+    /// height must still have the proof's hash, and the blob of its extrinsic
+    /// must hold the proof's entry under this database's app ID.
+    ///
+    /// A valid proof shows the node behind the backend knows a block with that
+    /// hash containing the entry. It is only as trustworthy as that node and,
+    /// unless the block is finalized, can still be undone by a reorg. It does
+    /// not prove the entry is the newest version of the key.
+    pub async fn verify_inclusion(&self, proof: &InclusionProof) -> Result<bool, DatabaseError> {
+        let height = proof.location.block_height;
+        let blocks = self.backend.get_blocks_by_range(self.app_id, height, height).await?;
+
+        Ok(blocks.iter().any(|block| {
+            block.hash == proof.location.block_hash
+                && block.blobs.iter().any(|blob| {
+                    blob.tx_index == proof.location.tx_index && blob.data.lines().any(|line| line == proof.entry)
+                })
+        }))
+    }
+
+    /// Fetches the blob at `location` and returns its first entry holding a
+    /// record that matches `predicate`
+    async fn find_entry(
+        &self,
+        location: &BlobLocation,
+        predicate: impl Fn(&Record) -> bool,
+    ) -> Result<Option<String>, DatabaseError> {
+        let height = location.block_height;
+        let blocks = self.backend.get_blocks_by_range(self.app_id, height, height).await?;
+
+        let entry = located_entries(&blocks)
+            .filter(|(found, _)| found == location)
+            .find(|(_, entry)| matches!(Blob::parse(entry), Some(Blob::Record(record)) if predicate(&record)))
+            .map(|(_, entry)| entry.to_string());
+
+        Ok(entry)
+    }
+
+    /// Checks whether a key currently has a live record. A key whose newest
+    /// entry is a tombstone does not exist.
+    ///
+    /// With a warm cache this stops at the newest entry for the key in the
+    /// blocks produced since, falling back to the cached state when they
+    /// don't mention it. A cold cache is warmed like any read, from the
+    /// latest snapshot and persisted to the state file, so a session of
+    /// adds pays for the history once rather than on every add; a key the
+    /// metadata's key filter rules out skips even that.
+    pub async fn key_exists(&self, key: &str) -> Result<bool, DatabaseError> {
+        if self.definitely_absent(key).await? {
+            return Ok(false);
+        }
+
+        let (scanned_height, cached) = {
+            let state = self.state.lock().unwrap();
+            (state.height, state.records.get(key).map(|record| !record.deleted))
+        };
+        let Some(scanned_height) = scanned_height else {
+            return Ok(self.latest_records().await?.contains_key(key));
+        };
+
+        let latest_block_height = self.backend.get_latest_block_height().await?;
+        let from_height = scanned_height + 1;
+
+        if from_height <= latest_block_height {
+            let blocks = self.backend
+                .get_blocks_by_range(self.app_id, from_height, latest_block_height)
+                .await?;
+
+            for (_, blob) in located_entries(&blocks) {
+                if let Some(Blob::Record(record)) = Blob::parse(blob) {
+                    if record.key == key {
+                        return Ok(!record.deleted);
+                    }
+                }
+            }
+        }
+
+        Ok(cached.unwrap_or(false))
+    }
+
+    /// Every version of a key across the database, newest first. Tombstones
+    /// are included so deletions show up in the timeline.
+    pub async fn get_record_history(&self, key: &str) -> Result<Vec<Record>, DatabaseError> {
+        let blocks = self.fetch_all_blocks().await?;
+
+        let versions = key_versions(&blocks, key);
+
+        // Chunked versions are listed once, at the position of their newest chunk
+        let mut chunks: HashMap<ChunkGroup, Vec<Record>> = HashMap::new();
+        for record in versions.iter().filter(|record| record.is_chunk()) {
+            chunks.entry(chunk_group(record)).or_default().push(record.clone());
+        }
+
+        let mut history = Vec::new();
+        for record in versions {
+            if !record.is_chunk() {
+                history.push(self.open(record)?);
+                continue;
+            }
+            let Some(parts) = chunks.remove(&chunk_group(&record)) else {
+                continue;
+            };
+            match assemble_chunks(parts) {
+                Some(assembled) => history.push(self.open(assembled)?),
+                None => warn!("Skipping incomplete chunked version {} of key '{}'", record.id, key),
+            }
+        }
+        info!("Found {} versions of key '{}'", history.len(), key);
+
+        Ok(history)
+    }
+
+    /// Reads a key as of block `height`: the newest version written at or
+    /// before that block. Returns `None` if the key did not exist yet, or had
+    /// been deleted, at that height.
+    pub async fn get_record_at_block(&self, key: &str, height: u32) -> Result<Option<Record>, DatabaseError> {
+        validate_key(key)?;
+        if height < self.start_height() {
+            return Ok(None);
+        }
+
+        info!(
+            "Searching for record with key '{}' as of block {} (blocks {}..={})",
+            key, height, self.start_height(), height
+        );
+        let blocks = self.backend
+            .get_blocks_by_range(self.app_id, self.start_height(), height)
+            .await?;
+        self.check_unrecognized(&blocks)?;
+
+        let versions = key_versions(&blocks, key);
+        let Some(newest) = versions.first() else {
+            return Ok(None);
+        };
+        if newest.deleted {
+            return Ok(None);
+        }
+        if !newest.is_chunk() {
+            return self.open(newest.clone()).map(Some);
+        }
+
+        let group = chunk_group(newest);
+        let total = newest.chunk_total.unwrap_or_default();
+        let parts: Vec<Record> = versions
+            .iter()
+            .filter(|record| record.is_chunk() && chunk_group(record) == group)
+            .cloned()
+            .collect();
+        let found = parts.len();
+
+        match assemble_chunks(parts) {
+            Some(assembled) => self.open(assembled).map(Some),
+            None => Err(DatabaseError::MissingChunks { key: key.to_string(), found, total }),
+        }
+    }
+
+    /// Reads a record by its `id`, scanning the full history. `update_record`
+    /// keeps the `id`, so this is the newest version written under that id,
+    /// even if the key has since been overwritten by `add` or deleted.
+    pub async fn get_by_id(&self, id: &str) -> Result<Option<Record>, DatabaseError> {
+        let blocks = self.fetch_all_blocks().await?;
+
+        let versions: Vec<Record> = located_entries(&blocks)
+            .filter_map(|(_, blob)| match Blob::parse(blob) {
+                Some(Blob::Record(record)) if record.id == id && !record.deleted => Some(record),
+                _ => None,
+            })
+            .collect();
+        let Some(newest) = versions.first() else {
+            return Ok(None);
+        };
+        if !newest.is_chunk() {
+            return self.open(newest.clone()).map(Some);
+        }
+
+        let group = chunk_group(newest);
+        let (key, total) = (newest.key.clone(), newest.chunk_total.unwrap_or_default());
+        let parts: Vec<Record> = versions
+            .into_iter()
+            .filter(|record| record.is_chunk() && chunk_group(record) == group)
+            .collect();
+        let found = parts.len();
+
+        match assemble_chunks(parts) {
+            Some(assembled) => self.open(assembled).map(Some),
+            None => Err(DatabaseError::MissingChunks { key, found, total }),
+        }
+    }
+
+    /// Fetches every block from the database start to the latest height,
+    /// bypassing the cached scan state
+    async fn fetch_all_blocks(&self) -> Result<Vec<BlockData>, DatabaseError> {
+        let latest_block_height = self.backend.get_latest_block_height().await?;
+        info!(
+            "Scanning full history (blocks {}..={})",
+            self.start_height(), latest_block_height
+        );
+
+        let blocks = self.backend
+            .get_blocks_by_range(self.app_id, self.start_height(), latest_block_height)
+            .await?;
+        self.check_unrecognized(&blocks)?;
+
+        Ok(blocks)
+    }
+
+    /// Returns the newest version of every live record, keyed by record key.
+    ///
+    /// Only blocks produced since the previous read are fetched. The delta is
+    /// folded newest-first (the first entry seen for a key wins), then every
+    /// key found in the delta replaces the cached entry. Since every block in
+    /// the delta is newer than every cached block, the newest entry for a key
+    /// is in the delta if the key appears there at all, and in the cached
+    /// state otherwise, so the result equals a newest-wins scan over the full
+    /// range. Tombstones are kept in the cached state so an older version of
+    /// a deleted key can never resurface.
+    ///
+    /// A key whose newest entry is a chunk is reassembled from the chunks
+    /// sharing its `id`. Chunks are written oldest-first over several blocks,
+    /// so a scan can stop between them: until every chunk has been seen, the
+    /// key is left out of the result and its chunks are kept in `partial` to
+    /// be completed by a later delta.
+    ///
+    /// On a cold cache the scan starts from the latest snapshot, if any: its
+    /// records are the fold of every block up to its height, so they serve as
+    /// the cached state and only newer blocks are scanned.
+    async fn latest_records(&self) -> Result<HashMap<String, Record>, DatabaseError> {
+        self.scan(true).await
+    }
+
+    /// `latest_records`, optionally ignoring snapshots so a cold cache is
+    /// rebuilt from a full scan
+    async fn scan(&self, use_snapshot: bool) -> Result<HashMap<String, Record>, DatabaseError> {
+        let latest_block_height = self.backend.get_latest_block_height().await?;
+
+        if use_snapshot && self.state.lock().unwrap().height.is_none() {
+            if let Some(state) = self.load_snapshot(latest_block_height).await? {
+                *self.state.lock().unwrap() = state;
+            }
+        }
+
+        let (scanned_height, mut records, mut partial, mut locations) = {
+            let state = self.state.lock().unwrap();
+            (state.height, state.records.clone(), state.partial.clone(), state.locations.clone())
+        };
+        let from_height = scanned_height.map(|h| h + 1).unwrap_or(self.start_height());
+
+        if from_height <= latest_block_height {
+            info!(
+                "Scanning records (blocks {}..={}, database start: {})",
+                from_height, latest_block_height, self.start_height()
+            );
+
+            let blocks = self.backend
+                .get_blocks_by_range(self.app_id, from_height, latest_block_height)
+                .await?;
+            self.check_unrecognized(&blocks)?;
+
+            let (delta, mut chunks) = newest_per_key(located_entries(&blocks));
+
+            for (key, (record, location)) in delta {
+                locations.insert(key.clone(), location);
+                if !record.is_chunk() {
+                    partial.remove(&key);
+                    records.insert(key, self.open(record)?);
+                    continue;
+                }
+
+                let group = chunk_group(&record);
+                let mut parts = chunks.remove(&group).unwrap_or_default();
+                if let Some(earlier) = partial.remove(&key) {
+                    parts.extend(earlier.into_iter().filter(|chunk| chunk_group(chunk) == group));
+                }
+
+                match assemble_chunks(parts.clone()) {
+                    Some(assembled) => {
+                        records.insert(key, self.open(assembled)?);
+                    }
+                    None => {
+                        records.remove(&key);
+                        partial.insert(key, parts);
+                    }
+                }
+            }
+
+            let state = ScanState {
+                height: Some(latest_block_height),
+                records: records.clone(),
+                partial: partial.clone(),
+                locations,
+            };
+            self.save_state_file(&state);
+            *self.state.lock().unwrap() = state;
+        }
+
+        for (key, parts) in &partial {
+            warn!(
+                "Skipping record '{}': found {} of {} chunks",
+                key, parts.len(), parts[0].chunk_total.unwrap_or_default()
+            );
+        }
+        records.retain(|_, record| !record.deleted);
+
+        Ok(records)
+    }
+
+    /// Builds the scan state from the snapshot at `metadata.snapshot_height`.
+    /// Returns `None` when there is no snapshot, or it is not readable yet
+    /// at `latest_block_height`.
+    async fn load_snapshot(&self, latest_block_height: u32) -> Result<Option<ScanState>, DatabaseError> {
+        let Some(snapshot_height) = self.metadata.as_ref().and_then(|m| m.snapshot_height) else {
+            return Ok(None);
+        };
+        let snapshot_height = snapshot_height as u32;
+        if snapshot_height > latest_block_height {
+            return Ok(None);
+        }
+
+        let blocks = self.backend
+            .get_blocks_by_range(self.app_id, snapshot_height, snapshot_height)
+            .await?;
+        let snapshot = located_entries(&blocks).find_map(|(_, entry)| match Blob::parse(entry) {
+            Some(Blob::Snapshot(snapshot)) => Some(snapshot),
+            _ => None,
+        });
+        let Some(snapshot) = snapshot else {
+            warn!("No snapshot found in block {}, scanning the full history", snapshot_height);
+            return Ok(None);
+        };
+        info!(
+            "Loaded snapshot of {} records as of block {} from block {}",
+            snapshot.records.len(), snapshot.height, snapshot_height
+        );
+
+        let mut state = ScanState {
+            height: Some(snapshot.height as u32),
+            locations: snapshot.locations,
+            ..ScanState::default()
+        };
+        for record in snapshot.records {
+            if record.is_chunk() {
+                state.partial.entry(record.key.clone()).or_default().push(record);
+            } else {
+                state.records.insert(record.key.clone(), self.open(record)?);
+            }
+        }
+
+        Ok(Some(state))
+    }
+
+    /// Lists the current version of every live record, sorted by `created_at`
+    /// ascending with ties broken by `key`, so the order is stable across runs
+    pub async fn list_records(&self) -> Result<Vec<Record>, DatabaseError> {
+        self.list_records_with(ReadConsistency::Strong).await
+    }
+
+    /// `list_records` at the given read consistency
+    pub async fn list_records_with(&self, consistency: ReadConsistency) -> Result<Vec<Record>, DatabaseError> {
+        let map = self.records_with(consistency).await?;
+        info!("Found {} records", map.len());
+
+        Ok(sorted_records(map))
+    }
+
+    /// `latest_records`, or for `ReadConsistency::Cached` the live records
+    /// of the cached state when there is one
+    async fn records_with(&self, consistency: ReadConsistency) -> Result<HashMap<String, Record>, DatabaseError> {
+        if consistency == ReadConsistency::Cached {
+            let state = self.state.lock().unwrap();
+            if let Some(height) = state.height {
+                info!("Serving cached state as of block {}", height);
+                let mut records = state.records.clone();
+                records.retain(|_, record| !record.deleted);
+                return Ok(records);
+            }
+        }
+
+        self.latest_records().await
+    }
+
+    /// Lists the live records created strictly after `after` and strictly
+    /// before `before` (either bound may be omitted), in `list_records` order
+    pub async fn list_records_filtered(
+        &self,
+        after: Option<chrono::DateTime<chrono::Utc>>,
+        before: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<Vec<Record>, DatabaseError> {
+        self.list_records_matching(&RecordFilter { created_after: after, created_before: before, tag: None })
+            .await
+    }
+
+    /// Lists the live records `filter` keeps, in `list_records` order
+    pub async fn list_records_matching(&self, filter: &RecordFilter) -> Result<Vec<Record>, DatabaseError> {
+        let mut map = self.latest_records().await?;
+        map.retain(|_, record| filter.matches(record));
+        info!("Found {} records matching the filter", map.len());
+
+        Ok(sorted_records(map))
+    }
+
+    /// Writes the current state of the database as a single snapshot blob and
+    /// records the block it landed in as the metadata's `snapshot_height`.
+    /// Fails with `BlobTooLarge` if the state doesn't fit in one blob.
+    pub async fn snapshot(&mut self) -> Result<SubmissionReceipt, DatabaseError> {
+        let records = self.latest_records().await?;
+
+        let (height, partial, mut locations) = {
+            let state = self.state.lock().unwrap();
+            (state.height.unwrap_or(self.start_height()), state.partial.clone(), state.locations.clone())
+        };
+        locations.retain(|key, _| records.contains_key(key) || partial.contains_key(key));
+
+        let mut entries = sorted_records(records)
+            .into_iter()
+            .map(|record| self.seal(record))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.extend(partial.into_values().flatten());
+
+        let count = entries.len();
+        let json = Blob::Snapshot(Snapshot {
+            height: height as u64,
+            created_at: chrono::Utc::now(),
+            records: entries,
+            locations,
+        }).to_json()?;
+
+        let receipt = self.submit(json).await?;
+        info!(
+            "Wrote snapshot of {} records as of block {} in block {}",
+            count, height, receipt.block_height
+        );
+
+        self.update_metadata(&receipt, |metadata| {
+            metadata.snapshot_height = Some(receipt.block_height as u64)
+        }).await?;
+
+        Ok(receipt)
+    }
+
+    /// Drops every in-memory cache, the backend's cached blocks and hashes
+    /// and the scanned state, and discovers the database's metadata again,
+    /// so the next read rescans from the chain. For when cached data is
+    /// suspected to be stale, e.g. after a reorg with `BlockFinality::Best`
+    /// reads. The state file, if any, is rewritten by that next read.
+    pub async fn clear_caches(&mut self) -> Result<(), DatabaseError> {
+        self.backend.clear_cache();
+        info!("Cleared the cached block hashes and blobs");
+
+        let cached_keys = std::mem::take(&mut *self.state.lock().unwrap()).records.len();
+        info!("Cleared the cached scan state ({} keys)", cached_keys);
+
+        match self.discover_database().await? {
+            Some((metadata, included)) => {
+                self.save_metadata_file(&metadata, &included);
+                self.metadata = Some(metadata)
+            }
+            None => warn!("Rediscovery found no metadata, keeping the metadata loaded at startup"),
+        }
+
+        Ok(())
+    }
+
+    /// Rebuilds the metadata from a full scan: drops the cached scan state,
+    /// recounts the distinct live keys and writes a corrected metadata blob.
+    /// Returns the record count before and after the repair.
+    pub async fn repair_metadata(&mut self) -> Result<(u64, u64), DatabaseError> {
+        let Some(mut metadata) = self.metadata.clone() else {
+            return Err(DatabaseError::NotFound("database metadata".to_string()));
+        };
+
+        *self.state.lock().unwrap() = ScanState::default();
+        let count = self.scan(false).await?.len() as u64;
+
+        let before = metadata.record_count;
+        metadata.record_count = count;
+        metadata.last_updated = chrono::Utc::now();
+
+        self.save_metadata(&metadata).await?;
+        self.metadata = Some(metadata);
+        info!("Repaired metadata: record count {} -> {}", before, count);
+
+        Ok((before, count))
+    }
+
+    /// Scans the whole database and compares the distinct live keys with the
+    /// metadata's `record_count`, without changing anything. A mismatch is
+    /// expected: `add_record` counts every add, including those overwriting
+    /// an existing key, and writes by other clients only update the count
+    /// their own metadata carries. `repair_metadata` corrects it.
+    pub async fn verify_metadata(&self) -> Result<MetadataReport, DatabaseError> {
+        let blocks = self.fetch_all_blocks().await?;
+
+        let (mut record_writes, mut tombstones) = (0, 0);
+        let mut seen = HashSet::new();
+        for (_, entry) in located_entries(&blocks) {
+            let Some(Blob::Record(record)) = Blob::parse(entry) else {
+                continue;
+            };
+            // A chunked write is counted at its first chunk
+            if record.chunk_index.unwrap_or(0) != 0 || !seen.insert(entry_id(&record)) {
+                continue;
+            }
+            if record.deleted {
+                tombstones += 1;
+            } else {
+                record_writes += 1;
+            }
+        }
+
+        let live_keys = self.latest_records().await?.len();
+        let metadata_count = self.metadata.as_ref().map(|m| m.record_count).unwrap_or_default();
+        info!(
+            "Verified metadata: {} records counted, {} live keys found",
+            metadata_count, live_keys
+        );
+
+        Ok(MetadataReport { metadata_count, live_keys, record_writes, tombstones })
+    }
+
+    /// Whether writes are refused, see `DatabaseClientConfig::read_only`
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// The raw blobs submitted under the app ID in the latest `block_range`
+    /// blocks, newest first, exactly as stored (after decompression) and
+    /// without any parsing, decryption or deduplication. Meant for debugging
+    /// entries that don't show up as expected.
+    pub async fn dump_raw(&self, block_range: u32) -> Result<Vec<String>, DatabaseError> {
+        self.backend.get_data_by_app_id(self.app_id, block_range).await
+    }
+
+    /// The blobs submitted under the app ID in the block at `height`, with
+    /// the block's hash and each blob's extrinsic index, unparsed. `None` if
+    /// the backend knows no such block.
+    pub async fn inspect_block(&self, height: u32) -> Result<Option<BlockData>, DatabaseError> {
+        let blocks = self.backend.get_blocks_by_range(self.app_id, height, height).await?;
+
+        Ok(blocks.into_iter().next())
+    }
+
+    /// A cursor at the latest block, from which `poll_new_records` reports
+    /// what is written afterwards
+    pub async fn tail_cursor(&self) -> Result<TailCursor, DatabaseError> {
+        let height = self.backend.get_latest_block_height().await?;
+
+        Ok(TailCursor { height, partial: HashMap::new(), reported: HashMap::new() })
+    }
+
+    /// A cursor from which `poll_new_records` reports what was written from
+    /// block `height` on, including blocks that already exist. Fails with
+    /// `InvalidInput` if `height` is above the latest readable block.
+    pub async fn tail_cursor_from(&self, height: u32) -> Result<TailCursor, DatabaseError> {
+        let latest_block_height = self.backend.get_latest_block_height().await?;
+        if height > latest_block_height {
+            return Err(DatabaseError::InvalidInput(format!(
+                "Block {} is above the latest readable block {}",
+                height, latest_block_height
+            )));
+        }
+
+        Ok(TailCursor { height: height.saturating_sub(1), partial: HashMap::new(), reported: HashMap::new() })
+    }
+
+    /// Every record entry included since the cursor's last poll, oldest first,
+    /// with where it was included, then advances the cursor. Tombstones are
+    /// included so deletions show up. A chunked record is returned once its
+    /// last chunk arrives, possibly several polls after the first.
+    pub async fn poll_new_records(
+        &self,
+        cursor: &mut TailCursor,
+    ) -> Result<Vec<(BlobLocation, Record)>, DatabaseError> {
+        let latest_block_height = self.backend.get_latest_block_height().await?;
+        if latest_block_height <= cursor.height {
+            return Ok(Vec::new());
+        }
+
+        let blocks = self.backend
+            .get_blocks_by_range(self.app_id, cursor.height + 1, latest_block_height)
+            .await?;
+        self.check_unrecognized(&blocks)?;
+
+        let entries: Vec<(BlobLocation, &str)> = located_entries(&blocks).collect();
+        let mut new_records = Vec::new();
+        for (location, entry) in entries.into_iter().rev() {
+            let Some(Blob::Record(record)) = Blob::parse(entry) else {
+                continue;
+            };
+            if cursor.reported.insert(entry_id(&record), location.block_height).is_some() {
+                info!("Skipping duplicate copy of a write to '{}' in block {}", record.key, location.block_height);
+                continue;
+            }
+            if !record.is_chunk() {
+                new_records.push((location, self.open(record)?));
+                continue;
+            }
+
+            let group = chunk_group(&record);
+            let parts = cursor.partial.entry(group.clone()).or_default();
+            parts.push(record);
+            if let Some(assembled) = assemble_chunks(parts.clone()) {
+                cursor.partial.remove(&group);
+                new_records.push((location, self.open(assembled)?));
+            }
+        }
+        cursor.height = latest_block_height;
+        cursor
+            .reported
+            .retain(|_, height| *height + DUPLICATE_WINDOW > latest_block_height);
+
+        Ok(new_records)
+    }
+
+    /// Sends every record entry written after `cursor`, as `poll_new_records`
+    /// returns them, to `sender` until its receiver is dropped.
+    ///
+    /// New blocks are announced by the backend's finalized block
+    /// subscription, and each announcement fetches everything after the
+    /// cursor. When the subscription drops, the watcher resubscribes, and
+    /// the first poll after that backfills the blocks produced in the gap.
+    /// Backends without a subscription are polled every `poll_interval`,
+    /// which is also the delay before resubscribing after a failure. Failed
+    /// polls are logged and retried from the same cursor, so no entry is
+    /// skipped.
+    pub async fn watch_records(
+        &self,
+        mut cursor: TailCursor,
+        poll_interval: Duration,
+        sender: mpsc::Sender<(BlobLocation, Record)>,
+    ) {
+        let mut subscribed_before = false;
+        loop {
+            let mut heights = match self.backend.subscribe_finalized_heights().await {
+                Ok(heights) => heights,
+                Err(e) => {
+                    warn!("Subscribing to finalized blocks failed, retrying: {}", e);
+                    tokio::select! {
+                        _ = tokio::time::sleep(poll_interval) => continue,
+                        _ = sender.closed() => return,
+                    }
+                }
+            };
+            if heights.is_some() && subscribed_before {
+                info!("Resubscribed to finalized blocks after block {}", cursor.height);
+            }
+            subscribed_before |= heights.is_some();
+
+            loop {
+                match self.poll_new_records(&mut cursor).await {
+                    Ok(new_records) => {
+                        for entry in new_records {
+                            if sender.send(entry).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    Err(e) => warn!("Polling for new records failed: {}", e),
+                }
+
+                match heights.as_mut() {
+                    Some(heights) => tokio::select! {
+                        next = heights.next() => match next {
+                            Some(Ok(_)) => {}
+                            Some(Err(e)) => {
+                                warn!("Finalized block subscription failed: {}", e);
+                                break;
+                            }
+                            None => {
+                                warn!("Finalized block subscription ended");
+                                break;
+                            }
+                        },
+                        _ = sender.closed() => return,
+                    },
+                    None => tokio::select! {
+                        _ = tokio::time::sleep(poll_interval) => {}
+                        _ = sender.closed() => return,
+                    },
+                }
+            }
+        }
+    }
+
+    /// Gathers size and health figures for the database with one full scan
+    /// of its blocks
+    pub async fn stats(&self) -> Result<DbStats, DatabaseError> {
+        let latest_height = self.backend.get_latest_block_height().await?;
+        let start_height = self.start_height();
+
+        let blocks = self.backend
+            .get_blocks_by_range(self.app_id, start_height, latest_height)
+            .await?;
+        let (mut total_blobs, mut unrecognized_blobs, mut duplicate_entries) = (0, 0, 0);
+        let mut seen = HashSet::new();
+        for (_, entry) in located_entries(&blocks) {
+            total_blobs += 1;
+            match Blob::parse(entry) {
+                Some(Blob::Record(record)) => duplicate_entries += !seen.insert(entry_id(&record)) as usize,
+                Some(_) => {}
+                None => unrecognized_blobs += 1,
+            }
+        }
+
+        let record_count = self.latest_records().await?.len();
+        let balance = self.backend.get_account_balance().await?;
+
+        Ok(DbStats {
+            app_id: self.app_id,
+            start_height: start_height as u64,
+            latest_height,
+            blocks_scanned: (latest_height as u64 + 1).saturating_sub(start_height as u64),
+            record_count,
+            total_blobs,
+            unrecognized_blobs,
+            duplicate_entries,
+            balance,
+        })
+    }
+
+    /// Lists the keys of every live record, sorted. Cheaper to display than
+    /// `list_records` when only the keys are needed.
+    pub async fn list_keys(&self) -> Result<Vec<String>, DatabaseError> {
+        let mut keys: Vec<String> = self.latest_records().await?.into_keys().collect();
+        keys.sort();
+        info!("Found {} keys", keys.len());
+
+        Ok(keys)
+    }
+
+    /// Lists the value of every live record, ordered by key. Deleted keys are
+    /// left out.
+    pub async fn list_values(&self) -> Result<Vec<String>, DatabaseError> {
+        let mut records: Vec<Record> = self.latest_records().await?.into_values().collect();
+        records.sort_by(|a, b| a.key.cmp(&b.key));
+        info!("Found {} values", records.len());
+
+        Ok(records.into_iter().map(|record| record.value).collect())
+    }
+
+    /// Streams every record entry of the database newest-first, fetching one
+    /// block at a time so databases too large to hold in memory can be
+    /// processed incrementally. Unlike `list_records`, entries are yielded
+    /// raw: older versions, tombstones and value chunks (still encrypted, if
+    /// encryption is on) are all included. Wrap the stream in `dedup_latest`
+    /// to get only the current version of each key.
+    pub fn list_records_stream(&self) -> impl Stream<Item = Result<Record, DatabaseError>> + '_ {
+        let start_height = self.start_height();
+
+        stream::once(self.backend.get_latest_block_height())
+            .map_ok(move |latest_block_height| {
+                stream::iter((start_height..=latest_block_height).rev())
+                    .then(move |height| self.backend.get_blocks_by_range(self.app_id, height, height))
+                    .map_ok(move |blocks| {
+                        let records: Vec<Result<Record, DatabaseError>> = located_entries(&blocks)
+                            .filter_map(|(_, blob)| match Blob::parse(blob) {
+                                Some(Blob::Record(record)) if record.is_chunk() => Some(Ok(record)),
+                                Some(Blob::Record(record)) => Some(self.open(record)),
+                                _ => None,
+                            })
+                            .collect();
+                        stream::iter(records)
+                    })
+                    .try_flatten()
+            })
+            .try_flatten()
+    }
+
+    /// Counts the distinct live keys. Logs a warning when the count disagrees
+    /// with `metadata.record_count`, which drifts because it is incremented on
+    /// every add, including overwrites of an existing key.
+    pub async fn count_records(&self) -> Result<usize, DatabaseError> {
+        let count = self.latest_records().await?.len();
+
+        if let Some(metadata) = &self.metadata {
+            if metadata.record_count != count as u64 {
+                warn!(
+                    "metadata reports {} records but {} distinct keys were found",
+                    metadata.record_count, count
+                );
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Lists the live records whose key starts with `prefix`, in the same
+    /// order as `list_records`
+    pub async fn list_records_by_prefix(&self, prefix: &str) -> Result<Vec<Record>, DatabaseError> {
+        let mut map = self.latest_records().await?;
+        map.retain(|key, _| key.starts_with(prefix));
+        info!("Found {} records with prefix '{}'", map.len(), prefix);
+
+        Ok(sorted_records(map))
+    }
+
+    /// Lists the live records whose key is in `start..=end`, sorted by key.
+    /// Keys compare as strings, byte by byte, so number parts need padding
+    /// to sort numerically, e.g. `order:0042`. Fails with
+    /// `DatabaseError::InvalidInput` if `start` sorts after `end`.
+    pub async fn list_records_in_range(&self, start: &str, end: &str) -> Result<Vec<Record>, DatabaseError> {
+        if start > end {
+            return Err(DatabaseError::InvalidInput(format!(
+                "Range start '{}' sorts after its end '{}'",
+                start, end
+            )));
+        }
+
+        let mut records: Vec<Record> = self
+            .latest_records()
+            .await?
+            .into_values()
+            .filter(|record| (start..=end).contains(&record.key.as_str()))
+            .collect();
+        records.sort_by(|a, b| a.key.cmp(&b.key));
+        info!("Found {} records from '{}' to '{}'", records.len(), start, end);
+
+        Ok(records)
+    }
+
+    /// Lists the live records whose value is JSON with `equals` at the JSON
+    /// pointer `path` (RFC 6901, e.g. `/address/city`), in the same order as
+    /// `list_records`. `equals` is parsed as JSON where it can be, so `30`
+    /// matches the number 30 and `true` the boolean; a string is also
+    /// matched by its text without quotes. Records whose value isn't JSON,
+    /// or has nothing at `path`, are skipped.
+    pub async fn query(&self, path: &str, equals: &str) -> Result<Vec<Record>, DatabaseError> {
+        if !path.is_empty() && !path.starts_with('/') {
+            return Err(DatabaseError::InvalidInput(format!(
+                "'{}' is not a JSON pointer: it must be empty or start with '/'",
+                path
+            )));
+        }
+        let target: Option<serde_json::Value> = serde_json::from_str(equals).ok();
+
+        let mut map = self.latest_records().await?;
+        map.retain(|_, record| {
+            let Ok(value) = serde_json::from_str::<serde_json::Value>(&record.value) else {
+                return false;
+            };
+            value
+                .pointer(path)
+                .is_some_and(|found| target.as_ref() == Some(found) || found.as_str() == Some(equals))
+        });
+        info!("Found {} records with {} = {}", map.len(), path, equals);
+
+        Ok(sorted_records(map))
+    }
+
+    /// Lists the live records tagged `tag`, in the same order as `list_records`
+    pub async fn list_records_by_tag(&self, tag: &str) -> Result<Vec<Record>, DatabaseError> {
+        self.list_records_matching(&RecordFilter { tag: Some(tag.to_string()), ..RecordFilter::default() })
+            .await
+    }
+
+    /// Writes the current state of the database to `path` as a pretty-printed
+    /// JSON array of records, in `list_records` order. An empty database is
+    /// written as `[]`.
+    pub async fn export_records(&self, path: &Path) -> Result<usize, DatabaseError> {
+        let records = self.list_records().await?;
+
+        let file = File::create(path)
+            .map_err(|e| DatabaseError::IoError(format!("Failed to create '{}': {}", path.display(), e)))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), &records)
+            .map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+
+        Ok(records.len())
+    }
+
+    /// Lists one page of records, in the same stable order as `list_records`
+    pub async fn list_records_paged(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> Result<RecordPage, DatabaseError> {
+        let records = self.list_records().await?;
+
+        let total = records.len();
+        let records = records.into_iter().skip(offset).take(limit).collect();
+
+        Ok(RecordPage { records, total })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+
+    fn entry(record: &Record) -> String {
+        Blob::Record(record.clone()).to_json().unwrap()
+    }
+
+    /// A block holding one blob
+    fn block(height: u32, data: String) -> BlockData {
+        BlockData {
+            height,
+            hash: avail_rust::prelude::H256::from_low_u64_be(height as u64),
+            blobs: vec![crate::avail::BlockBlob { tx_index: 0, data }],
+        }
+    }
+
+    /// `(key, id, deleted, block_height)` in key order, for comparing folds:
+    /// `Record` has no `PartialEq`
+    fn summary(records: &HashMap<String, (Record, BlobLocation)>) -> Vec<(String, String, bool, u32)> {
+        let mut summary: Vec<_> = records
+            .values()
+            .map(|(record, location)| {
+                (record.key.clone(), record.id.clone(), record.deleted, location.block_height)
+            })
+            .collect();
+        summary.sort();
+        summary
+    }
+
+    #[test]
+    fn folding_new_blocks_over_cached_state_equals_full_fold() {
+        let a = Record::new("a".to_string(), "1".to_string());
+        let b = Record::new("b".to_string(), "2".to_string());
+        let c = Record::new("c".to_string(), "3".to_string());
+        let a_again = Record::new("a".to_string(), "1 again".to_string());
+        let b_deleted = Record::tombstone("b".to_string());
+        let d = Record::new("d".to_string(), "4".to_string());
+
+        // Blocks newest first, as fetched: the range already folded into the
+        // cached state, then the blocks produced since, with a batch blob in each
+        let cached_blocks = [block(2, entry(&c)), block(1, format!("{}\n{}", entry(&a), entry(&b)))];
+        let new_blocks = [block(4, format!("{}\n{}", entry(&b_deleted), entry(&d))), block(3, entry(&a_again))];
+
+        let mut incremental = newest_per_key(located_entries(&cached_blocks)).0;
+        incremental.extend(newest_per_key(located_entries(&new_blocks)).0);
+
+        let all_blocks: Vec<BlockData> = new_blocks.iter().chain(&cached_blocks).cloned().collect();
+        let full = newest_per_key(located_entries(&all_blocks)).0;
+
+        assert_eq!(summary(&incremental), summary(&full));
+        assert_eq!(incremental["a"].0.value, "1 again");
+        assert!(incremental["b"].0.deleted);
+        assert_eq!(incremental.len(), 4);
+    }
+
+    /// Opens the database on `backend` with a cold cache
+    async fn client_on(backend: &MemoryBackend) -> DatabaseClient {
+        DatabaseClient::new(Box::new(backend.clone()), 1, Some(10)).await.unwrap()
+    }
+
+    async fn memory_client() -> DatabaseClient {
+        client_on(&MemoryBackend::default()).await
+    }
+
+    /// Live records as JSON in `list_records` order, for comparing reads:
+    /// `Record` has no `PartialEq`
+    fn as_json(records: HashMap<String, Record>) -> serde_json::Value {
+        serde_json::to_value(sorted_records(records)).unwrap()
+    }
+
+    /// What a fresh client reads by folding every block from the database
+    /// start, snapshots ignored
+    async fn full_scan(backend: &MemoryBackend) -> serde_json::Value {
+        let db = client_on(backend).await;
+        as_json(db.scan(false).await.unwrap())
+    }
+
+    fn keys(records: &[Record]) -> Vec<&str> {
+        records.iter().map(|record| record.key.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn added_record_can_be_read_back() {
+        let mut db = memory_client().await;
+
+        let record = Record::new("user:1".to_string(), "alice".to_string()).with_tags(vec!["users".to_string()]);
+        db.add_record(record.clone()).await.unwrap();
+
+        let found = db.get_record("user:1").await.unwrap().unwrap();
+        assert_eq!(found.value, "alice");
+        assert_eq!(found.id, record.id);
+        assert_eq!(found.tags, ["users"]);
+        assert_eq!(found.version, 1);
+        assert!(db.get_record("user:2").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn newest_write_wins() {
+        let mut db = memory_client().await;
+
+        db.add_record(Record::new("color".to_string(), "red".to_string())).await.unwrap();
+        db.add_record(Record::new("color".to_string(), "blue".to_string())).await.unwrap();
+
+        assert_eq!(db.get_record("color").await.unwrap().unwrap().value, "blue");
+        let records = db.list_records().await.unwrap();
+        assert_eq!(keys(&records), ["color"]);
+        assert_eq!(records[0].value, "blue");
+    }
+
+    #[tokio::test]
+    async fn deleted_key_can_be_added_again() {
+        let mut db = memory_client().await;
+
+        db.add_record(Record::new("session".to_string(), "first".to_string())).await.unwrap();
+        db.delete_record("session").await.unwrap();
+
+        assert!(db.get_record("session").await.unwrap().is_none());
+        assert!(!db.key_exists("session").await.unwrap());
+        assert!(db.list_records().await.unwrap().is_empty());
+        assert!(matches!(db.delete_record("session").await, Err(DatabaseError::NotFound(_))));
+
+        db.add_record(Record::new("session".to_string(), "second".to_string())).await.unwrap();
+
+        let found = db.get_record("session").await.unwrap().unwrap();
+        assert_eq!(found.value, "second");
+        assert_eq!(found.version, 1);
+        assert!(db.key_exists("session").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn list_records_holds_every_live_key_in_creation_order() {
+        let mut db = memory_client().await;
+
+        for (key, value) in [("a", "1"), ("b", "2"), ("c", "3")] {
+            db.add_record(Record::new(key.to_string(), value.to_string())).await.unwrap();
+        }
+        db.delete_record("b").await.unwrap();
+
+        let records = db.list_records().await.unwrap();
+        assert_eq!(keys(&records), ["a", "c"]);
+        assert_eq!(records[0].value, "1");
+        assert_eq!(records[1].value, "3");
+        assert_eq!(db.count_records().await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn filter_applies_tag_and_time_bounds_together() {
+        let mut db = memory_client().await;
+        let start = chrono::Utc::now() - chrono::Duration::hours(3);
+
+        for (hours, key, tag) in [(0, "a", "x"), (1, "b", "x"), (2, "c", "y"), (3, "d", "x")] {
+            let mut record = Record::new(key.to_string(), key.to_string()).with_tags(vec![tag.to_string()]);
+            record.created_at = start + chrono::Duration::hours(hours);
+            db.add_record(record).await.unwrap();
+        }
+
+        let filter = RecordFilter {
+            created_after: Some(start),
+            created_before: None,
+            tag: Some("x".to_string()),
+        };
+        assert_eq!(keys(&db.list_records_matching(&filter).await.unwrap()), ["b", "d"]);
+        assert_eq!(keys(&db.list_records_by_tag("x").await.unwrap()), ["a", "b", "d"]);
+        let before = start + chrono::Duration::hours(2);
+        assert_eq!(keys(&db.list_records_filtered(None, Some(before)).await.unwrap()), ["a", "b"]);
+        assert_eq!(keys(&db.list_records_matching(&RecordFilter::default()).await.unwrap()), ["a", "b", "c", "d"]);
+    }
+
+    #[tokio::test]
+    async fn snapshot_plus_delta_equals_full_scan() {
+        let backend = MemoryBackend::default();
+        let mut db = client_on(&backend).await;
+        // Large values are only chunked under a small blob limit, but the
+        // snapshot holds whole values and needs the default one
+        let default_limit = db.max_blob_size;
+        let chunked_limit = 1000;
+
+        db.max_blob_size = chunked_limit;
+        for (key, value) in [("kept", "1"), ("overwritten", "old"), ("deleted", "doomed"), ("readded", "first")] {
+            db.add_record(Record::new(key.to_string(), value.to_string())).await.unwrap();
+        }
+        db.add_record(Record::new("chunked".to_string(), "a".repeat(3000))).await.unwrap();
+        db.delete_record("readded").await.unwrap();
+
+        db.max_blob_size = default_limit;
+        db.snapshot().await.unwrap();
+
+        db.max_blob_size = chunked_limit;
+        db.add_record(Record::new("overwritten".to_string(), "new".to_string())).await.unwrap();
+        db.delete_record("deleted").await.unwrap();
+        db.add_record(Record::new("readded".to_string(), "second".to_string())).await.unwrap();
+        db.add_record(Record::new("chunked".to_string(), "b".repeat(3000))).await.unwrap();
+        db.add_record(Record::new("chunked-later".to_string(), "c".repeat(2500))).await.unwrap();
+        assert!(backend.blobs().iter().any(|blob| blob.contains("\"chunk_index\"")), "no value was chunked");
+
+        let seeded = client_on(&backend).await;
+        let latest_block_height = backend.get_latest_block_height().await.unwrap();
+        assert!(seeded.load_snapshot(latest_block_height).await.unwrap().is_some());
+
+        let records = seeded.latest_records().await.unwrap();
+        assert_eq!(records["kept"].value, "1");
+        assert_eq!(records["overwritten"].value, "new");
+        assert!(!records.contains_key("deleted"));
+        assert_eq!(records["readded"].value, "second");
+        assert_eq!(records["chunked"].value, "b".repeat(3000));
+        assert_eq!(records["chunked-later"].value, "c".repeat(2500));
+
+        assert_eq!(as_json(records), full_scan(&backend).await);
+    }
+
+    #[tokio::test]
+    async fn incremental_fold_equals_full_rescan() {
+        let backend = MemoryBackend::default();
+        let mut db = client_on(&backend).await;
+
+        for (key, value) in [("a", "1"), ("b", "2"), ("c", "3")] {
+            db.add_record(Record::new(key.to_string(), value.to_string())).await.unwrap();
+        }
+        assert_eq!(db.list_records().await.unwrap().len(), 3);
+        let cached_height = db.state.lock().unwrap().height.unwrap();
+
+        db.add_record(Record::new("a".to_string(), "1 again".to_string())).await.unwrap();
+        db.delete_record("b").await.unwrap();
+        db.add_records(vec![
+            Record::new("d".to_string(), "4".to_string()),
+            Record::new("c".to_string(), "3 again".to_string()),
+        ]).await.unwrap();
+        // Written by another client, so only a scan of the new blocks finds it
+        let mut other = client_on(&backend).await;
+        other.add_record(Record::new("e".to_string(), "5".to_string())).await.unwrap();
+
+        let records = db.latest_records().await.unwrap();
+        assert!(db.state.lock().unwrap().height.unwrap() > cached_height);
+        let mut live_keys: Vec<&str> = records.keys().map(String::as_str).collect();
+        live_keys.sort();
+        assert_eq!(live_keys, ["a", "c", "d", "e"]);
+        assert_eq!(records["a"].value, "1 again");
+        assert_eq!(records["c"].value, "3 again");
+        assert!(db.state.lock().unwrap().records["b"].deleted);
+
+        assert_eq!(as_json(records), full_scan(&backend).await);
+    }
+
+    #[tokio::test]
+    async fn concurrent_fetch_of_shared_blocks_reads_like_a_serial_one() {
+        let backend = MemoryBackend::default().blobs_per_block(3).concurrency(4);
+        let mut db = client_on(&backend).await;
+
+        // Overwrites land in the same block as the writes they replace, so
+        // the newest write is picked by extrinsic index, not block height
+        for i in 0..12 {
+            let key = format!("k{}", i % 4);
+            db.add_record(Record::new(key, i.to_string())).await.unwrap();
+        }
+        db.delete_record("k1").await.unwrap();
+
+        let records = db.list_records().await.unwrap();
+        assert_eq!(keys(&records), ["k0", "k2", "k3"]);
+        let values: Vec<&str> = records.iter().map(|record| record.value.as_str()).collect();
+        assert_eq!(values, ["8", "10", "11"]);
+
+        let concurrent = full_scan(&backend).await;
+        assert_eq!(concurrent, full_scan(&backend.clone().concurrency(1)).await);
+        assert_eq!(concurrent, serde_json::to_value(&records).unwrap());
+    }
+
+    #[test]
+    fn located_entries_are_newest_first_whatever_the_fetch_order() {
+        let block_with = |height: u32, blobs: &[(u32, &str)]| BlockData {
+            height,
+            hash: avail_rust::prelude::H256::from_low_u64_be(height as u64),
+            blobs: blobs
+                .iter()
+                .map(|&(tx_index, data)| crate::avail::BlockBlob { tx_index, data: data.to_string() })
+                .collect(),
+        };
+        // Blocks as `buffer_unordered` may complete them, blobs in extrinsic
+        // order, and a batch blob holding three entries
+        let blocks = [
+            block_with(3, &[(0, "3.0"), (2, "3.2a\n3.2b\n3.2c")]),
+            block_with(8, &[(1, "8.1")]),
+            block_with(5, &[(4, "5.4"), (1, "5.1")]),
+        ];
 
-        Ok(map.into_values().collect())
+        let order: Vec<(u32, u32, &str)> = located_entries(&blocks)
+            .map(|(location, entry)| (location.block_height, location.tx_index, entry))
+            .collect();
+        assert_eq!(
+            order,
+            [(8, 1, "8.1"), (5, 4, "5.4"), (5, 1, "5.1"), (3, 2, "3.2c"), (3, 2, "3.2b"), (3, 2, "3.2a"), (3, 0, "3.0")]
+        );
     }
 }
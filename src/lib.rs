@@ -0,0 +1,18 @@
+//! A public key-value database on Avail, addressed by an application key (app name)
+
+pub mod avail;
+pub mod backend;
+pub mod bloom;
+pub mod config;
+pub mod crypto;
+pub mod db;
+pub mod log;
+pub mod schema;
+
+pub use backend::{AvailBackend, AvailRpcBackend, MemoryBackend};
+pub use config::DatabaseClientConfig;
+pub use db::{dedup_latest, DatabaseClient, ReadConsistency, RecordFilter, TailCursor};
+pub use schema::{
+    validate_key, DatabaseError, DatabaseMetadata, DbStats, InclusionProof, MetadataReport, Record,
+    MAX_KEY_LENGTH,
+};
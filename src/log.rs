@@ -0,0 +1,43 @@
+use std::io;
+
+use tracing_subscriber::fmt::time::ChronoLocal;
+use tracing_subscriber::EnvFilter;
+
+/// Format of the local time prefixed to each log line
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.3f";
+
+/// How much `init_logging` lets through
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Verbosity {
+    /// Warnings and errors only
+    Quiet,
+    /// `RUST_LOG` if set, `info` otherwise
+    #[default]
+    Normal,
+    /// `info`, plus this crate's `debug` lines such as each block fetched.
+    /// Dependencies stay at `info`: their debug output is mostly RPC traffic.
+    Verbose,
+}
+
+/// Sets up logging to stderr, so stdout only carries command output, at the
+/// given verbosity. Without `timestamps`, lines start at the level, e.g. for
+/// JSON mode or tests that compare log output.
+///
+/// Installs the global subscriber, so it must be called at most once.
+pub fn init_logging(timestamps: bool, verbosity: Verbosity) {
+    let filter = match verbosity {
+        Verbosity::Quiet => EnvFilter::new("warn"),
+        Verbosity::Normal => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        Verbosity::Verbose => EnvFilter::new("info,da_db_implementation=debug"),
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_target(false)
+        .with_writer(io::stderr);
+
+    if timestamps {
+        builder.with_timer(ChronoLocal::new(TIMESTAMP_FORMAT.to_string())).init();
+    } else {
+        builder.without_time().init();
+    }
+}
@@ -1,22 +1,153 @@
-mod avail;
-mod schema;
-mod db;
+mod server;
 
-use db::DatabaseClient;
-use schema::{DatabaseError, Record};
-use std::io::{self, Write};
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::{Parser, Subcommand};
+use da_db_implementation::avail::{self, BlobLocation, BlockData, SubmissionReceipt};
+use da_db_implementation::config::app_id_from_env;
+use da_db_implementation::log::{init_logging, Verbosity};
+use da_db_implementation::schema::Blob;
+use da_db_implementation::{
+    AvailRpcBackend, DatabaseClient, DatabaseClientConfig, DatabaseError, DbStats, MetadataReport, Record,
+    RecordFilter,
+};
+use serde::Serialize;
+use serde_json::json;
+use std::cell::Cell;
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::pin::pin;
 use std::str::FromStr;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
 
-/// Helper function to get current timestamp for logging
-fn log_with_timestamp(message: &str) {
-    let timestamp = chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f");
-    println!("[{}] {}", timestamp, message);
+/// Page size used by `list <offset>` when no limit is given
+const DEFAULT_PAGE_SIZE: usize = 20;
+
+/// Seconds between polls of `tail` when no interval is given
+const DEFAULT_TAIL_INTERVAL_SECS: u64 = 5;
+
+/// Operations `bench` runs of each kind when no count is given
+const DEFAULT_BENCH_OPS: usize = 10;
+
+/// Arguments of `list`
+#[derive(Default)]
+struct ListArgs {
+    /// One page of `(offset, limit)`, or all records when `None`
+    page: Option<(usize, usize)>,
+    filter: RecordFilter,
+    /// Print every record in full instead of as a table
+    verbose: bool,
+}
+
+/// Parses an RFC 3339 timestamp such as `2025-06-23T12:00:00Z`
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .map_err(|e| format!("'{}' is not an RFC 3339 timestamp (e.g. 2025-06-23T12:00:00Z): {}", value, e))
+}
+
+/// Splits a command line into words on whitespace. Double quotes wrapping a
+/// whole word group it verbatim, whitespace included, so `add key "a  b"`
+/// stores `a  b` exactly; inside them, `\"` and `\\` stand for a literal
+/// quote and backslash. Quotes anywhere else, as in the JSON value
+/// `{"name": "Naruto"}`, are taken literally, like everything else. Once a
+/// word opens with `{` or `[`, a JSON value has started and the rest of the
+/// line is taken literally too, so `{"a": "b" }` keeps the quotes around `b`.
+fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut current: Option<String> = None;
+    let mut in_json = false;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if current.is_none() && !in_json => {
+                let mut rest = chars.clone();
+                let word = quoted_word(&mut rest)?;
+                if rest.clone().next().is_none_or(char::is_whitespace) {
+                    tokens.push(word);
+                    chars = rest;
+                } else {
+                    current = Some(c.to_string());
+                }
+            }
+            c if c.is_whitespace() => tokens.extend(current.take()),
+            c => {
+                if current.is_none() && matches!(c, '{' | '[') {
+                    in_json = true;
+                }
+                current.get_or_insert_with(String::new).push(c);
+            }
+        }
+    }
+    tokens.extend(current);
+
+    Ok(tokens)
+}
+
+/// Reads a quoted word up to its closing quote, whose opening quote was
+/// just consumed from `chars`, resolving `\"` and `\\`
+fn quoted_word(chars: &mut std::str::Chars<'_>) -> Result<String, String> {
+    let mut word = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(word),
+            Some('\\') => match chars.next() {
+                Some(escaped @ ('"' | '\\')) => word.push(escaped),
+                Some(other) => {
+                    word.push('\\');
+                    word.push(other);
+                }
+                None => return Err("Unbalanced quote: missing closing \"".to_string()),
+            },
+            Some(other) => word.push(other),
+            None => return Err("Unbalanced quote: missing closing \"".to_string()),
+        }
+    }
 }
 
 enum Command {
-    Add(String, String),
+    /// Key, value and tags
+    Add(String, String, Vec<String>),
+    AddBinary(String, String),
+    Batch(String),
+    Update(String, String),
+    Merge(String, String),
+    /// Key, expected version and new value
+    Cas(String, u64, String),
+    SetNx(String, String),
+    Incr(String, i64),
     Get(String),
-    List,
+    GetAt(String, u32),
+    GetId(String),
+    Delete(String),
+    Clear { confirmed: bool },
+    Exists(String),
+    History(String),
+    Search(String),
+    /// Inclusive start and end keys
+    Range(String, String),
+    /// JSON pointer and the value it must equal
+    Query(String, String),
+    Keys,
+    Values,
+    Count,
+    Stats,
+    Dump(u32),
+    Inspect(u32),
+    /// Poll interval in seconds, and the block to start from instead of the
+    /// latest
+    Tail { interval: u64, from: Option<u32> },
+    Bench { writes: usize, reads: usize },
+    Verify,
+    Repair,
+    Refresh,
+    Snapshot,
+    Compact,
+    Export(String),
+    List(ListArgs),
     Exit,
     Help,
 }
@@ -25,21 +156,108 @@ impl FromStr for Command {
     type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let parts: Vec<&str> = s.trim().split_whitespace().collect();
+        Self::from_words(&tokenize(s)?)
+    }
+}
+
+impl Command {
+    /// Parses a command already split into words, e.g. by the shell
+    fn from_words(words: &[String]) -> Result<Self, String> {
+        let parts: Vec<&str> = words.iter().map(String::as_str).collect();
         if parts.is_empty() {
             return Err("Empty command".to_string());
         }
 
         match parts[0].to_lowercase().as_str() {
             "add" => {
+                let usage = "Invalid add command format. Usage: add [--tag <tag>]... <key> <value>";
+
+                // Tags come before the key, so a value may itself contain `--tag`
+                let mut tags = Vec::new();
+                let mut rest = &parts[1..];
+                while let ["--tag", tag, remaining @ ..] = rest {
+                    tags.push(tag.to_string());
+                    rest = remaining;
+                }
+                if rest.len() < 2 {
+                    return Err(usage.to_string());
+                }
+
+                let key = rest[0].to_string();
+                let value = rest[1..].join(" ");
+
+                Ok(Command::Add(key, value, tags))
+            }
+            "add-binary" => {
+                if parts.len() != 3 {
+                    return Err("Invalid add-binary command format. Usage: add-binary <key> <path>".to_string());
+                }
+
+                Ok(Command::AddBinary(parts[1].to_string(), parts[2].to_string()))
+            }
+            "update" => {
+                if parts.len() < 3 {
+                    return Err("Invalid update command format. Usage: update <key> <value>".to_string());
+                }
+
+                let key = parts[1].to_string();
+                let value = parts[2..].join(" ");
+
+                Ok(Command::Update(key, value))
+            }
+            "merge" => {
+                if parts.len() < 3 {
+                    return Err("Invalid merge command format. Usage: merge <key> <json>".to_string());
+                }
+
+                let key = parts[1].to_string();
+                let patch = parts[2..].join(" ");
+
+                Ok(Command::Merge(key, patch))
+            }
+            "cas" => {
+                if parts.len() < 4 {
+                    return Err("Invalid cas command format. Usage: cas <key> <version> <value>".to_string());
+                }
+
+                let key = parts[1].to_string();
+                let version = parts[2]
+                    .parse::<u64>()
+                    .map_err(|_| "version must be a valid number".to_string())?;
+                let value = parts[3..].join(" ");
+
+                Ok(Command::Cas(key, version, value))
+            }
+            "setnx" => {
                 if parts.len() < 3 {
-                    return Err("Invalid add command format. Usage: add <key> <value>".to_string());
+                    return Err("Invalid setnx command format. Usage: setnx <key> <value>".to_string());
                 }
-                
+
                 let key = parts[1].to_string();
                 let value = parts[2..].join(" ");
 
-                Ok(Command::Add(key, value))
+                Ok(Command::SetNx(key, value))
+            }
+            "incr" => {
+                if parts.len() != 2 && parts.len() != 3 {
+                    return Err("Invalid incr command format. Usage: incr <key> [delta]".to_string());
+                }
+
+                let delta = match parts.get(2) {
+                    Some(delta) => delta
+                        .parse::<i64>()
+                        .map_err(|_| "delta must be a valid integer".to_string())?,
+                    None => 1,
+                };
+
+                Ok(Command::Incr(parts[1].to_string(), delta))
+            }
+            "batch" => {
+                if parts.len() != 2 {
+                    return Err("Invalid batch command format. Usage: batch <file>".to_string());
+                }
+
+                Ok(Command::Batch(parts[1].to_string()))
             }
             "get" => {
                 if parts.len() != 2 {
@@ -48,182 +266,1555 @@ impl FromStr for Command {
 
                 Ok(Command::Get(parts[1].to_string()))
             }
-            "list" => Ok(Command::List),
+            "get-at" => {
+                if parts.len() != 3 {
+                    return Err("Invalid get-at command format. Usage: get-at <key> <height>".to_string());
+                }
+
+                let height = parts[2]
+                    .parse::<u32>()
+                    .map_err(|_| "height must be a valid block number".to_string())?;
+
+                Ok(Command::GetAt(parts[1].to_string(), height))
+            }
+            "get-id" => {
+                if parts.len() != 2 {
+                    return Err("Invalid get-id command format. Usage: get-id <uuid>".to_string());
+                }
+
+                Ok(Command::GetId(parts[1].to_string()))
+            }
+            "delete" => {
+                if parts.len() != 2 {
+                    return Err("Invalid delete command format. Usage: delete <key>".to_string());
+                }
+
+                Ok(Command::Delete(parts[1].to_string()))
+            }
+            "clear" => match parts.get(1) {
+                None if parts.len() == 1 => Ok(Command::Clear { confirmed: false }),
+                Some(&"--yes") if parts.len() == 2 => Ok(Command::Clear { confirmed: true }),
+                _ => Err("Invalid clear command format. Usage: clear [--yes]".to_string()),
+            },
+            "exists" => {
+                if parts.len() != 2 {
+                    return Err("Invalid exists command format. Usage: exists <key>".to_string());
+                }
+
+                Ok(Command::Exists(parts[1].to_string()))
+            }
+            "history" => {
+                if parts.len() != 2 {
+                    return Err("Invalid history command format. Usage: history <key>".to_string());
+                }
+
+                Ok(Command::History(parts[1].to_string()))
+            }
+            "search" => {
+                if parts.len() != 2 {
+                    return Err("Invalid search command format. Usage: search <prefix>".to_string());
+                }
+
+                Ok(Command::Search(parts[1].to_string()))
+            }
+            "range" => {
+                if parts.len() != 3 {
+                    return Err("Invalid range command format. Usage: range <start> <end>".to_string());
+                }
+
+                Ok(Command::Range(parts[1].to_string(), parts[2].to_string()))
+            }
+            "query" => {
+                if parts.len() < 3 {
+                    return Err("Invalid query command format. Usage: query <json-pointer> <value>".to_string());
+                }
+
+                Ok(Command::Query(parts[1].to_string(), parts[2..].join(" ")))
+            }
+            "list" => {
+                let usage = "Invalid list command format. Usage: list [offset] [limit] \
+                             [--created-after <rfc3339>] [--created-before <rfc3339>] [--tag <tag>] [--verbose]";
+
+                let mut list = ListArgs::default();
+                let mut positional = Vec::new();
+                let mut args = parts[1..].iter();
+                while let Some(arg) = args.next() {
+                    match *arg {
+                        "--created-after" => {
+                            let value = args.next().ok_or_else(|| usage.to_string())?;
+                            list.filter.created_after = Some(parse_timestamp(value)?);
+                        }
+                        "--created-before" => {
+                            let value = args.next().ok_or_else(|| usage.to_string())?;
+                            list.filter.created_before = Some(parse_timestamp(value)?);
+                        }
+                        "--tag" => {
+                            let value = args.next().ok_or_else(|| usage.to_string())?;
+                            list.filter.tag = Some(value.to_string());
+                        }
+                        "--verbose" => list.verbose = true,
+                        _ => positional.push(*arg),
+                    }
+                }
+                if positional.len() > 2 {
+                    return Err(usage.to_string());
+                }
+
+                if let Some(offset) = positional.first() {
+                    let offset = offset
+                        .parse::<usize>()
+                        .map_err(|_| "offset must be a valid number".to_string())?;
+                    let limit = match positional.get(1) {
+                        Some(limit) => limit
+                            .parse::<usize>()
+                            .map_err(|_| "limit must be a valid number".to_string())?,
+                        None => DEFAULT_PAGE_SIZE,
+                    };
+                    list.page = Some((offset, limit));
+                }
+
+                Ok(Command::List(list))
+            }
+            "keys" => Ok(Command::Keys),
+            "values" => Ok(Command::Values),
+            "count" => Ok(Command::Count),
+            "stats" => Ok(Command::Stats),
+            "inspect" => {
+                if parts.len() != 2 {
+                    return Err("Invalid inspect command format. Usage: inspect <height>".to_string());
+                }
+
+                let height = parts[1]
+                    .parse::<u32>()
+                    .map_err(|_| "height must be a valid block number".to_string())?;
+
+                Ok(Command::Inspect(height))
+            }
+            "dump" => {
+                if parts.len() != 2 {
+                    return Err("Invalid dump command format. Usage: dump <blocks>".to_string());
+                }
+
+                let blocks = parts[1]
+                    .parse::<u32>()
+                    .map_err(|_| "blocks must be a valid number".to_string())?;
+
+                Ok(Command::Dump(blocks))
+            }
+            "tail" => {
+                let usage = "Invalid tail command format. Usage: tail [--interval <secs>] [--from <height>]";
+
+                let (mut interval, mut from) = (DEFAULT_TAIL_INTERVAL_SECS, None);
+                let mut args = parts[1..].iter();
+                while let Some(arg) = args.next() {
+                    let value = args.next().ok_or_else(|| usage.to_string())?;
+                    match *arg {
+                        "--interval" => {
+                            interval = value
+                                .parse::<u64>()
+                                .ok()
+                                .filter(|secs| *secs > 0)
+                                .ok_or_else(|| "interval must be a positive number of seconds".to_string())?;
+                        }
+                        "--from" => {
+                            from = Some(
+                                value
+                                    .parse::<u32>()
+                                    .map_err(|_| "from must be a valid block height".to_string())?,
+                            );
+                        }
+                        _ => return Err(usage.to_string()),
+                    }
+                }
+
+                Ok(Command::Tail { interval, from })
+            }
+            "bench" => {
+                let usage = "Invalid bench command format. Usage: bench [--writes <n>] [--reads <n>]";
+
+                let (mut writes, mut reads) = (DEFAULT_BENCH_OPS, DEFAULT_BENCH_OPS);
+                let mut args = parts[1..].iter();
+                while let Some(arg) = args.next() {
+                    let count = match *arg {
+                        "--writes" => &mut writes,
+                        "--reads" => &mut reads,
+                        _ => return Err(usage.to_string()),
+                    };
+                    *count = args
+                        .next()
+                        .ok_or_else(|| usage.to_string())?
+                        .parse::<usize>()
+                        .map_err(|_| format!("{} must be a valid number", arg))?;
+                }
+
+                Ok(Command::Bench { writes, reads })
+            }
+            "repair" => Ok(Command::Repair),
+            "verify" => Ok(Command::Verify),
+            "refresh" => Ok(Command::Refresh),
+            "snapshot" => Ok(Command::Snapshot),
+            "compact" => Ok(Command::Compact),
+            "export" => {
+                if parts.len() != 2 {
+                    return Err("Invalid export command format. Usage: export <path>".to_string());
+                }
+
+                Ok(Command::Export(parts[1].to_string()))
+            }
             "exit" | "quit" => Ok(Command::Exit),
             "help" => Ok(Command::Help),
             _ => Err(format!("Unknown command: {}", parts[0])),
         }
     }
+
+    /// Whether the command submits anything. Writes are never cancelled
+    /// halfway, so Ctrl-C waits for them to finish.
+    fn is_write(&self) -> bool {
+        matches!(
+            self,
+            Command::Add(..)
+                | Command::AddBinary(..)
+                | Command::Batch(_)
+                | Command::Update(..)
+                | Command::Merge(..)
+                | Command::Cas(..)
+                | Command::SetNx(..)
+                | Command::Incr(..)
+                | Command::Delete(_)
+                | Command::Clear { .. }
+                | Command::Repair
+                | Command::Snapshot
+                | Command::Compact
+                | Command::Bench { .. }
+        )
+    }
 }
 
-async fn handle_command(
-    db: &mut DatabaseClient,
-    command: Command
+/// REPL commands and their descriptions, as shown by `help`
+const COMMANDS: &[(&str, &str)] = &[
+    ("add <key> <value>", "Add a new record or update existing one"),
+    ("  --tag <tag>", "Tag the record; repeat for several tags"),
+    ("add-binary <key> <path>", "Add a file's bytes as a base64-encoded record"),
+    ("update <key> <value>", "Update an existing record, keeping its creation time"),
+    ("merge <key> <json>", "Merge a JSON object into a record's JSON value"),
+    ("cas <key> <version> <value>", "Update a record only if it is still at a version"),
+    ("setnx <key> <value>", "Add a record only if the key doesn't exist yet"),
+    ("incr <key> [delta]", "Add delta (default 1) to an integer record"),
+    ("batch <file>", "Add all <key> <value> lines of a file in one submission"),
+    ("get <key>", "Retrieve a record by key"),
+    ("get-at <key> <height>", "Retrieve a record as it was at a block height"),
+    ("get-id <uuid>", "Retrieve a record by its id"),
+    ("delete <key>", "Delete a record by key"),
+    ("clear [--yes]", "Delete every record, after confirming"),
+    ("list [offset] [limit]", "List all records, or one page of them"),
+    ("  --created-after <ts>", "Only records created after an RFC 3339 timestamp"),
+    ("  --created-before <ts>", "Only records created before an RFC 3339 timestamp"),
+    ("  --tag <tag>", "Only records with a tag"),
+    ("  --verbose", "Print each record in full instead of as a table"),
+    ("exists <key>", "Check whether a key exists"),
+    ("history <key>", "Show every version of a key, newest first"),
+    ("search <prefix>", "List records whose key starts with a prefix"),
+    ("range <start> <end>", "List records with keys from start to end, inclusive"),
+    ("query <json-pointer> <value>", "List records whose JSON value has a value at a pointer"),
+    ("keys", "List the keys of all records"),
+    ("values", "List the values of all records, ordered by key"),
+    ("count", "Count the distinct live keys"),
+    ("stats", "Show the size and health of the database"),
+    ("dump <blocks>", "Print the raw blobs of the latest blocks, unparsed"),
+    ("inspect <height>", "Show each blob in a block and what it parses as"),
+    ("tail", "Print new records as their blocks are finalized"),
+    ("  --interval <secs>", "Seconds between polls when not subscribed"),
+    ("  --from <height>", "Start from a block instead of the latest"),
+    ("bench", "Measure the throughput and latency of adds and gets"),
+    ("  --writes <n>", "Records to add (default 10)"),
+    ("  --reads <n>", "Gets to run on the added keys (default 10)"),
+    ("verify", "Check the metadata's record count against a full scan"),
+    ("repair", "Recount the records with a full scan and fix the metadata"),
+    ("refresh", "Drop all cached chain data and rediscover the database"),
+    ("snapshot", "Write the current state as a single snapshot blob"),
+    ("compact", "Rewrite the live records and skip the older history in future scans"),
+    ("export <path>", "Write all records to a JSON file"),
+    ("exit", "Exit the application"),
+    ("help", "Show this help message"),
+];
+
+/// Prints the list of REPL commands
+fn print_help() {
+    println!("\nAvailable commands:");
+    for (usage, description) in COMMANDS {
+        println!("  {:<24} - {}", usage, description);
+    }
+}
+
+/// What a blob entry parses as, for `inspect`
+fn blob_kind(entry: &str) -> &'static str {
+    match Blob::parse(entry) {
+        Some(Blob::Metadata(_)) => "metadata",
+        Some(Blob::Record(record)) if record.deleted => "tombstone",
+        Some(Blob::Record(record)) if record.is_chunk() => "record chunk",
+        Some(Blob::Record(_)) => "record",
+        Some(Blob::Snapshot(_)) => "snapshot",
+        None => "unknown",
+    }
+}
+
+/// Asks a yes/no question; only `yes` counts as agreement, and end of
+/// input as no. The answer is read from the prompt's own reader, since an
+/// answer typed ahead or piped in may already sit in its buffer. The
+/// question goes to stderr so `--json` output stays machine-readable.
+async fn confirm(stdin: &mut BufReader<tokio::io::Stdin>, question: &str) -> Result<bool, DatabaseError> {
+    eprint!("{}", question);
+    io::stderr().flush().map_err(|e| DatabaseError::IoError(e.to_string()))?;
+
+    let mut answer = String::new();
+    stdin
+        .read_line(&mut answer)
+        .await
+        .map_err(|e| DatabaseError::IoError(e.to_string()))?;
+
+    Ok(answer.trim().eq_ignore_ascii_case("yes"))
+}
+
+/// Prints a record in the human-readable `Key:`/`Value:` format
+fn print_record(record: &Record) {
+    println!("Key: {}", record.key);
+    match record.value_bytes() {
+        Some(bytes) => println!("Value: <{} bytes> {}", bytes.len(), record.value),
+        None => println!("Value: {}", record.value),
+    }
+    if let Some(encoding) = &record.encoding {
+        println!("Encoding: {}", encoding);
+    }
+    if !record.tags.is_empty() {
+        println!("Tags: {}", record.tags.join(", "));
+    }
+    println!("Created: {}", record.created_at.to_rfc3339());
+    if let Some(updated) = record.updated_at {
+        println!("Updated At: {}", updated);
+    }
+    println!("Version: {}", record.version);
+}
+
+/// Parses a batch file with one `<key> <value>` pair per line.
+/// Blank lines and lines starting with `#` are ignored.
+fn parse_batch(contents: &str) -> Result<Vec<Record>, DatabaseError> {
+    let mut records = Vec::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once(char::is_whitespace) else {
+            return Err(DatabaseError::InvalidInput(format!(
+                "line {}: expected <key> <value>",
+                index + 1
+            )));
+        };
+
+        records.push(Record::new(key.to_string(), value.trim().to_string()));
+    }
+
+    Ok(records)
+}
+
+/// How command results are written to stdout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    /// The human-readable `Key:`/`Value:` format
+    Text,
+    /// One JSON value per command, for scripts and `jq`
+    Json,
+}
+
+/// Prints `value` as a single line of JSON
+fn print_json(value: &impl Serialize) -> Result<(), DatabaseError> {
+    let json = serde_json::to_string(value).map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+    println!("{}", json);
+    Ok(())
+}
+
+/// The JSON form of a submission receipt
+fn receipt_json(receipt: &SubmissionReceipt) -> serde_json::Value {
+    json!({
+        "tx_hash": format!("{:?}", receipt.tx_hash),
+        "block_hash": format!("{:?}", receipt.block_hash),
+        "block_height": receipt.block_height,
+    })
+}
+
+/// Widest value shown in a `print_table` cell, in characters
+const TABLE_VALUE_WIDTH: usize = 40;
+
+/// `value` on one line and cut to at most `width` characters, ending in an
+/// ellipsis when it was cut
+fn truncate_cell(value: &str, width: usize) -> String {
+    let line: String = value
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+    if line.chars().count() <= width {
+        return line;
+    }
+
+    let mut cut: String = line.chars().take(width.saturating_sub(1)).collect();
+    cut.push('…');
+    cut
+}
+
+/// Prints records as a table of key, value (truncated), creation and update time
+fn print_table(records: &[Record]) {
+    let headers = ["KEY", "VALUE", "CREATED", "UPDATED"];
+    let rows: Vec<[String; 4]> = records
+        .iter()
+        .map(|record| {
+            [
+                truncate_cell(&record.key, usize::MAX),
+                truncate_cell(&record.value, TABLE_VALUE_WIDTH),
+                record.created_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+                record
+                    .updated_at
+                    .map(|updated| updated.to_rfc3339_opts(SecondsFormat::Secs, true))
+                    .unwrap_or_else(|| "-".to_string()),
+            ]
+        })
+        .collect();
+
+    let mut widths = headers.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let print_row = |cells: [&str; 4]| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{}{}", cell, " ".repeat(width - cell.chars().count())))
+            .collect();
+        println!("{}", padded.join(" | ").trim_end());
+    };
+    let separator: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+
+    print_row(headers);
+    println!("{}", separator.join("-+-"));
+    for row in &rows {
+        print_row([row[0].as_str(), row[1].as_str(), row[2].as_str(), row[3].as_str()]);
+    }
+}
+
+/// Prints a list of records, or `empty_message` when there are none; as a
+/// table unless `verbose`, in which case every record is printed in full
+fn print_records(
+    records: &[Record],
+    output: OutputFormat,
+    empty_message: &str,
+    verbose: bool,
 ) -> Result<(), DatabaseError> {
-    match command {
-        Command::Add(key, value) => {
-            log_with_timestamp(&format!("Adding record with key: {}", key));
+    if output == OutputFormat::Json {
+        return print_json(&records);
+    }
+
+    if records.is_empty() {
+        println!("{}", empty_message);
+    } else if !verbose {
+        print_table(records);
+    } else {
+        for record in records {
+            print_record(record);
+            println!("---");
+        }
+    }
+
+    Ok(())
+}
+
+/// Timings of one kind of operation run by `bench`
+#[derive(Serialize)]
+struct BenchResult {
+    operation: &'static str,
+    count: usize,
+    total_ms: f64,
+    ops_per_sec: f64,
+    p50_ms: f64,
+    p95_ms: f64,
+}
 
-            let record = Record::new(key, value);
-            db.add_record(record).await?;
+impl BenchResult {
+    fn new(operation: &'static str, mut latencies: Vec<Duration>) -> Self {
+        latencies.sort();
+        let total: Duration = latencies.iter().sum();
+        // Nearest-rank percentile
+        let percentile = |p: f64| {
+            let rank = ((p * latencies.len() as f64).ceil() as usize).clamp(1, latencies.len().max(1));
+            latencies.get(rank - 1).copied().unwrap_or_default()
+        };
+        let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
 
-            log_with_timestamp(&format!("Record added successfully"));
+        Self {
+            operation,
+            count: latencies.len(),
+            total_ms: ms(total),
+            ops_per_sec: if total.is_zero() { 0.0 } else { latencies.len() as f64 / total.as_secs_f64() },
+            p50_ms: ms(percentile(0.50)),
+            p95_ms: ms(percentile(0.95)),
         }
-        Command::Get(key) => {
-            log_with_timestamp(&format!("Getting record with key: '{}'", key));
-
-            match db.get_record(&key).await? {
-                Some(record) => {
-                    println!("Key: {}", record.key);
-                    println!("Value: {}", record.value);
-                    println!("Created: {}", record.created_at.to_rfc3339());
-                    if let Some(updated) = record.updated_at {
-                        println!("Updated At: {}", updated);
+    }
+}
+
+/// The `bench` summary as a table, one row per operation
+fn bench_table(results: &[BenchResult]) -> String {
+    let mut lines = vec![format!(
+        "{:<10} {:>6} {:>12} {:>10} {:>10} {:>10}",
+        "OPERATION", "COUNT", "TOTAL (ms)", "OPS/SEC", "P50 (ms)", "P95 (ms)"
+    )];
+    for result in results {
+        lines.push(format!(
+            "{:<10} {:>6} {:>12.1} {:>10.2} {:>10.1} {:>10.1}",
+            result.operation, result.count, result.total_ms, result.ops_per_sec, result.p50_ms, result.p95_ms
+        ));
+    }
+
+    lines.join("\n")
+}
+
+/// Adds `writes` records under fresh `bench:` keys, then gets `reads` of
+/// them round-robin, through the same `add_record` and `get_record` calls
+/// as the `add` and `get` commands. Without writes the reads go to the
+/// existing keys.
+async fn run_bench(db: &mut DatabaseClient, writes: usize, reads: usize) -> Result<Vec<BenchResult>, DatabaseError> {
+    let run = Utc::now().timestamp_millis();
+    let mut keys = Vec::with_capacity(writes);
+
+    let mut write_latencies = Vec::with_capacity(writes);
+    for i in 0..writes {
+        let key = format!("bench:{}:{}", run, i);
+        let started = Instant::now();
+        db.add_record(Record::new(key.clone(), format!("bench value {}", i))).await?;
+        let elapsed = started.elapsed();
+        info!("Benchmark write {}/{} took {:?}", i + 1, writes, elapsed);
+        write_latencies.push(elapsed);
+        keys.push(key);
+    }
+
+    if keys.is_empty() && reads > 0 {
+        keys = db.list_keys().await?;
+    }
+    let mut read_latencies = Vec::with_capacity(reads);
+    if !keys.is_empty() {
+        for i in 0..reads {
+            let started = Instant::now();
+            db.get_record(&keys[i % keys.len()]).await?;
+            read_latencies.push(started.elapsed());
+        }
+    } else if reads > 0 {
+        warn!("No keys to read, skipping the benchmark reads");
+    }
+
+    Ok(vec![
+        BenchResult::new("add", write_latencies),
+        BenchResult::new("get", read_latencies),
+    ])
+}
+
+/// What a command produced, printed by `render` in the selected format
+enum CommandOutput {
+    /// A short result: `text` is printed in text mode (nothing when `None`,
+    /// the log lines already tell the story) and `json` in JSON mode
+    Message { text: Option<String>, json: serde_json::Value },
+    /// A single record, with the timestamp of its block when known. In text
+    /// mode a missing record prints nothing; the command logs it instead.
+    Record { record: Option<Record>, block_time: Option<DateTime<Utc>> },
+    /// A list of records, as a table unless `verbose`
+    Records { records: Vec<Record>, empty_message: String, verbose: bool },
+    /// Every version of a key, newest first
+    History { key: String, history: Vec<Record> },
+    Keys(Vec<String>),
+    Values(Vec<String>),
+    Stats(DbStats),
+    Verify(MetadataReport),
+    /// The raw blobs of the latest `blocks` blocks
+    Blobs { blocks: u32, blobs: Vec<String> },
+    /// The block at `height`, `None` if it doesn't exist
+    Block { height: u32, block: Option<BlockData> },
+    /// A record entry `tail` picked up, with where it was included
+    NewRecord(BlobLocation, Record),
+    /// Nothing left to print
+    Done,
+    Help,
+    Exit,
+}
+
+/// A `CommandOutput::Message` printing nothing in text mode
+fn silent(json: serde_json::Value) -> CommandOutput {
+    CommandOutput::Message { text: None, json }
+}
+
+/// Serializes a value that always has a JSON form, such as a record
+fn to_json_value(value: &impl Serialize) -> Result<serde_json::Value, DatabaseError> {
+    serde_json::to_value(value).map_err(|e| DatabaseError::SerializationError(e.to_string()))
+}
+
+/// Prints the result of a command to stdout
+fn render(result: CommandOutput, output: OutputFormat) -> Result<(), DatabaseError> {
+    match result {
+        CommandOutput::Message { text, json } => match output {
+            OutputFormat::Json => print_json(&json)?,
+            OutputFormat::Text => {
+                if let Some(text) = text {
+                    println!("{}", text);
+                }
+            }
+        },
+        CommandOutput::Record { record, block_time } => match (output, record) {
+            (OutputFormat::Json, Some(record)) => {
+                let mut json = to_json_value(&record)?;
+                if let Some(block_time) = block_time {
+                    json["block_time"] = json!(block_time);
+                }
+                print_json(&json)?;
+            }
+            (OutputFormat::Json, None) => print_json(&None::<Record>)?,
+            (OutputFormat::Text, Some(record)) => {
+                print_record(&record);
+                if let Some(block_time) = block_time {
+                    println!("Block time: {}", block_time.to_rfc3339());
+                }
+            }
+            (OutputFormat::Text, None) => {}
+        },
+        CommandOutput::Records { records, empty_message, verbose } => {
+            print_records(&records, output, &empty_message, verbose)?;
+        }
+        CommandOutput::History { key, history } => {
+            if output == OutputFormat::Json {
+                print_json(&history)?;
+            } else if history.is_empty() {
+                println!("No history found for key '{}'", key);
+            } else {
+                for record in history {
+                    if record.deleted {
+                        println!("Deleted: {}", record.created_at.to_rfc3339());
+                    } else {
+                        print_record(&record);
                     }
+                    println!("---");
                 }
-                None => log_with_timestamp(&format!("No record found with key: '{}'", key)),
             }
         }
-        Command::List => {
-            let records = db.list_records().await?;
-
-            if records.is_empty() {
+        CommandOutput::Keys(lines) | CommandOutput::Values(lines) => {
+            if output == OutputFormat::Json {
+                print_json(&lines)?;
+            } else if lines.is_empty() {
                 println!("No records found");
             } else {
-                for record in records {
-                    println!("Key: {}", record.key);
-                    println!("Value: {}", record.value);
-                    println!("Created: {}", record.created_at.to_rfc3339());
+                for line in lines {
+                    println!("{}", line);
+                }
+            }
+        }
+        CommandOutput::Stats(stats) => {
+            if output == OutputFormat::Json {
+                print_json(&stats)?;
+            } else {
+                println!("App ID: {}", stats.app_id);
+                println!("Start height: {}", stats.start_height);
+                println!("Latest height: {}", stats.latest_height);
+                println!("Blocks scanned: {}", stats.blocks_scanned);
+                println!("Records: {}", stats.record_count);
+                println!("Blobs: {}", stats.total_blobs);
+                println!("Unrecognized blobs: {}", stats.unrecognized_blobs);
+                println!("Duplicate entries: {}", stats.duplicate_entries);
+                if let Some(balance) = stats.balance {
+                    println!("Account balance: {}", avail::format_avail(balance));
+                }
+            }
+        }
+        CommandOutput::Verify(report) => {
+            if output == OutputFormat::Json {
+                print_json(&json!({
+                    "metadata_count": report.metadata_count,
+                    "live_keys": report.live_keys,
+                    "record_writes": report.record_writes,
+                    "tombstones": report.tombstones,
+                    "delta": report.delta(),
+                }))?;
+            } else {
+                println!("Metadata record count: {}", report.metadata_count);
+                println!("Live keys: {}", report.live_keys);
+                println!("Record writes: {}", report.record_writes);
+                println!("Deletions: {}", report.tombstones);
+                match report.delta() {
+                    0 => println!("OK: the metadata matches the data"),
+                    delta if delta > 0 => println!(
+                        "Mismatch: the metadata counts {} more records than there are. This is expected \
+                         when adds overwrite existing keys, since each add counts a new record",
+                        delta
+                    ),
+                    delta => println!(
+                        "Mismatch: the metadata counts {} fewer records than there are, usually because \
+                         another client wrote records and this client's metadata is older than theirs",
+                        -delta
+                    ),
+                }
+            }
+        }
+        CommandOutput::Blobs { blocks, blobs } => {
+            if output == OutputFormat::Json {
+                print_json(&blobs)?;
+            } else if blobs.is_empty() {
+                println!("No blobs in the latest {} blocks", blocks);
+            } else {
+                for (index, blob) in blobs.iter().enumerate() {
+                    println!("[{}] {}", index, blob);
+                }
+            }
+        }
+        CommandOutput::Block { height, block } => {
+            let blobs = block.as_ref().map(|block| block.blobs.as_slice()).unwrap_or_default();
 
-                    if let Some(updated) = record.updated_at {
-                        println!("Updated At: {}", updated);
-                    }
+            let entries: Vec<(u32, &'static str, &str)> = blobs
+                .iter()
+                .flat_map(|blob| blob.data.lines().map(move |line| (blob.tx_index, blob_kind(line), line)))
+                .collect();
 
-                    println!("---");
+            if output == OutputFormat::Json {
+                let entries: Vec<serde_json::Value> = entries
+                    .iter()
+                    .map(|(tx_index, kind, data)| json!({ "tx_index": tx_index, "kind": kind, "data": data }))
+                    .collect();
+                print_json(&json!({
+                    "height": height,
+                    "hash": block.as_ref().map(|block| format!("{:?}", block.hash)),
+                    "entries": entries,
+                }))?;
+            } else {
+                match &block {
+                    Some(block) => println!("Block {} ({:?}): {} entries", height, block.hash, entries.len()),
+                    None => println!("Block {} not found", height),
+                }
+                for (tx_index, kind, data) in entries {
+                    println!("[tx {}] {}: {}", tx_index, kind, data);
                 }
             }
         }
-        Command::Exit => {
-            log_with_timestamp("Exiting application");
+        CommandOutput::NewRecord(location, record) => {
+            if output == OutputFormat::Json {
+                let mut json = to_json_value(&record)?;
+                json["block_height"] = json!(location.block_height);
+                print_json(&json)?;
+            } else if record.deleted {
+                println!("[block {}] Deleted: {}", location.block_height, record.key);
+                println!("---");
+            } else {
+                println!("[block {}]", location.block_height);
+                print_record(&record);
+                println!("---");
+            }
+        }
+        CommandOutput::Done => {}
+        CommandOutput::Help => print_help(),
+        CommandOutput::Exit => {
+            info!("Exiting application");
             std::process::exit(0);
         }
-        Command::Help => {
-            println!("\nAvailable commands:");
-            println!("  add <key> <value>  - Add a new record or update existing one");
-            println!("  get <key>          - Retrieve a record by key");
-            println!("  list               - List all records");
-            println!("  exit               - Exit the application");
-            println!("  help               - Show this help message");
+    }
+
+    Ok(())
+}
+
+/// Runs a command against the database and returns what it produced,
+/// without printing anything but log lines. `tail` produces its output as
+/// it goes, so it renders each new record itself in the `output` format and
+/// runs until cancelled.
+async fn handle_command(
+    db: &mut DatabaseClient,
+    command: Command,
+    output: OutputFormat,
+) -> Result<CommandOutput, DatabaseError> {
+    let result = match command {
+        Command::Add(key, value, tags) => {
+            info!("Adding record with key: {}", key);
+
+            let record = Record::new(key, value).with_tags(tags);
+            let receipt = db.add_record(record).await?;
+
+            info!(
+                "Record added in block {} (tx {:?})",
+                receipt.block_height, receipt.tx_hash
+            );
+            silent(receipt_json(&receipt))
+        }
+        Command::AddBinary(key, path) => {
+            info!("Adding binary record with key: {} from {}", key, path);
+
+            let bytes = std::fs::read(&path)
+                .map_err(|e| DatabaseError::IoError(format!("Failed to read '{}': {}", path, e)))?;
+            let size = bytes.len();
+            let receipt = db.add_record(Record::new_binary(key, bytes)).await?;
+
+            info!(
+                "Binary record ({} bytes) added in block {} (tx {:?})",
+                size, receipt.block_height, receipt.tx_hash
+            );
+            silent(receipt_json(&receipt))
+        }
+        Command::Update(key, value) => {
+            info!("Updating record with key: {}", key);
+
+            let record = db.update_record(&key, value).await?;
+
+            info!(
+                "Record updated successfully (created {}, updated {})",
+                record.created_at.to_rfc3339(),
+                record.updated_at.map(|t| t.to_rfc3339()).unwrap_or_default()
+            );
+            silent(to_json_value(&record)?)
+        }
+        Command::Merge(key, patch) => {
+            info!("Merging into record with key: {}", key);
+
+            let record = db.merge_record(&key, &patch).await?;
+
+            info!("Record merged, new value: {}", record.value);
+            silent(to_json_value(&record)?)
+        }
+        Command::Cas(key, version, value) => {
+            info!("Updating record with key: {} if it is at version {}", key, version);
+
+            let record = db.compare_and_swap(&key, version, value).await?;
+
+            info!("Record updated to version {}", record.version);
+            silent(to_json_value(&record)?)
+        }
+        Command::SetNx(key, value) => {
+            info!("Adding record with key: {} if absent", key);
+
+            let written = db.put_if_absent(&key, value).await?;
+
+            if written {
+                info!("Record added");
+            } else {
+                info!("Key '{}' already exists, nothing written", key);
+            }
+            silent(json!({ "written": written }))
+        }
+        Command::Incr(key, delta) => {
+            info!("Incrementing record with key: {} by {}", key, delta);
+
+            let value = db.increment(&key, delta).await?;
+
+            CommandOutput::Message {
+                text: Some(value.to_string()),
+                json: json!({ "key": key, "value": value }),
+            }
+        }
+        Command::Batch(path) => {
+            info!("Loading batch file: {}", path);
+
+            let contents = std::fs::read_to_string(&path)
+                .map_err(|e| DatabaseError::IoError(format!("Failed to read '{}': {}", path, e)))?;
+            let records = parse_batch(&contents)?;
+
+            let count = records.len();
+            match db.add_records(records).await? {
+                Some(receipt) => {
+                    info!(
+                        "Added {} records in block {} (tx {:?})",
+                        count, receipt.block_height, receipt.tx_hash
+                    );
+                    let mut json = receipt_json(&receipt);
+                    json["added"] = json!(count);
+                    silent(json)
+                }
+                None => {
+                    info!("Batch file contains no records");
+                    silent(json!({ "added": 0 }))
+                }
+            }
+        }
+        Command::Get(key) => {
+            info!("Getting record with key: '{}'", key);
+
+            let found = db.get_record_with_block_time(&key).await?;
+            if found.is_none() {
+                info!("No record found with key: '{}'", key);
+            }
+            let (record, block_time) = found.unzip();
+            CommandOutput::Record { record, block_time }
+        }
+        Command::GetAt(key, height) => {
+            info!("Getting record with key: '{}' at block {}", key, height);
+
+            let record = db.get_record_at_block(&key, height).await?;
+            if record.is_none() {
+                info!("No record found with key: '{}' at block {}", key, height);
+            }
+            CommandOutput::Record { record, block_time: None }
+        }
+        Command::GetId(id) => {
+            info!("Getting record with id: '{}'", id);
+
+            let record = db.get_by_id(&id).await?;
+            if record.is_none() {
+                info!("No record found with id: '{}'", id);
+            }
+            CommandOutput::Record { record, block_time: None }
+        }
+        Command::Delete(key) => {
+            info!("Deleting record with key: '{}'", key);
+
+            let receipt = db.delete_record(&key).await?;
+
+            info!(
+                "Record deleted in block {} (tx {:?})",
+                receipt.block_height, receipt.tx_hash
+            );
+            silent(receipt_json(&receipt))
+        }
+        Command::Clear { .. } => {
+            let (count, receipt) = db.clear().await?;
+
+            match &receipt {
+                Some(receipt) => info!(
+                    "Cleared {} records, last tombstone in block {} (tx {:?})",
+                    count, receipt.block_height, receipt.tx_hash
+                ),
+                None => info!("Nothing to clear"),
+            }
+            let mut json = receipt.as_ref().map(receipt_json).unwrap_or_else(|| json!({}));
+            json["cleared"] = json!(count);
+            silent(json)
+        }
+        Command::List(list) => {
+            let records = db.list_records_matching(&list.filter).await?;
+
+            let records = match list.page {
+                Some((offset, limit)) => {
+                    let total = records.len();
+                    let page: Vec<Record> = records.into_iter().skip(offset).take(limit).collect();
+                    info!("Showing {} of {} records (offset {})", page.len(), total, offset);
+                    page
+                }
+                None => records,
+            };
+
+            CommandOutput::Records {
+                records,
+                empty_message: "No records found".to_string(),
+                verbose: list.verbose,
+            }
+        }
+        Command::Exists(key) => {
+            let exists = db.key_exists(&key).await?;
+
+            CommandOutput::Message { text: Some(exists.to_string()), json: json!(exists) }
+        }
+        Command::History(key) => {
+            let history = db.get_record_history(&key).await?;
+
+            CommandOutput::History { key, history }
+        }
+        Command::Search(prefix) => {
+            let records = db.list_records_by_prefix(&prefix).await?;
+
+            CommandOutput::Records {
+                records,
+                empty_message: format!("No records found with prefix '{}'", prefix),
+                verbose: true,
+            }
+        }
+        Command::Range(start, end) => {
+            let records = db.list_records_in_range(&start, &end).await?;
+
+            CommandOutput::Records {
+                records,
+                empty_message: format!("No records found from '{}' to '{}'", start, end),
+                verbose: true,
+            }
+        }
+        Command::Query(path, equals) => {
+            let records = db.query(&path, &equals).await?;
+
+            CommandOutput::Records {
+                records,
+                empty_message: format!("No records found with {} = {}", path, equals),
+                verbose: true,
+            }
+        }
+        Command::Keys => CommandOutput::Keys(db.list_keys().await?),
+        Command::Values => CommandOutput::Values(db.list_values().await?),
+        Command::Count => {
+            let count = db.count_records().await?;
+
+            CommandOutput::Message { text: Some(format!("Records: {}", count)), json: json!(count) }
+        }
+        Command::Stats => CommandOutput::Stats(db.stats().await?),
+        Command::Dump(blocks) => CommandOutput::Blobs { blocks, blobs: db.dump_raw(blocks).await? },
+        Command::Inspect(height) => CommandOutput::Block { height, block: db.inspect_block(height).await? },
+        Command::Tail { interval, from } => {
+            let cursor = match from {
+                Some(height) => db.tail_cursor_from(height).await?,
+                None => db.tail_cursor().await?,
+            };
+            info!("Watching for new records after block {} (Ctrl-C to stop)", cursor.height());
+
+            let (sender, mut receiver) = mpsc::channel(64);
+            let watch = db.watch_records(cursor, Duration::from_secs(interval), sender);
+            let print = async move {
+                while let Some((location, record)) = receiver.recv().await {
+                    render(CommandOutput::NewRecord(location, record), output)?;
+                }
+                Ok::<_, DatabaseError>(())
+            };
+
+            // The watcher runs until the printer drops the receiver, which
+            // only happens if printing fails
+            let ((), printed) = tokio::join!(watch, print);
+            printed?;
+            CommandOutput::Done
+        }
+        Command::Verify => {
+            info!("Verifying metadata with a full scan");
+
+            CommandOutput::Verify(db.verify_metadata().await?)
+        }
+        Command::Repair => {
+            info!("Repairing metadata with a full scan");
+
+            let (before, after) = db.repair_metadata().await?;
+
+            CommandOutput::Message {
+                text: Some(format!("Record count: {} -> {}", before, after)),
+                json: json!({ "before": before, "after": after }),
+            }
+        }
+        Command::Bench { writes, reads } => {
+            warn!("Benchmarking: {} records will be written under bench: keys", writes);
+
+            let results = run_bench(db, writes, reads).await?;
+
+            CommandOutput::Message { text: Some(bench_table(&results)), json: to_json_value(&results)? }
+        }
+        Command::Refresh => {
+            info!("Clearing caches");
+
+            db.clear_caches().await?;
+
+            silent(json!({ "refreshed": true }))
+        }
+        Command::Snapshot => {
+            info!("Writing snapshot");
+
+            let receipt = db.snapshot().await?;
+
+            info!(
+                "Snapshot written in block {} (tx {:?})",
+                receipt.block_height, receipt.tx_hash
+            );
+            silent(receipt_json(&receipt))
+        }
+        Command::Compact => {
+            info!("Compacting the database");
+
+            let (count, start_height) = db.compact().await?;
+
+            CommandOutput::Message {
+                text: Some(format!("Rewrote {} records, the database now starts at block {}", count, start_height)),
+                json: json!({ "records": count, "start_height": start_height }),
+            }
+        }
+        Command::Export(path) => {
+            info!("Exporting records to: {}", path);
+
+            let count = db.export_records(Path::new(&path)).await?;
+
+            info!("Exported {} records", count);
+            silent(json!({ "path": path, "exported": count }))
+        }
+        Command::Exit => CommandOutput::Exit,
+        Command::Help => CommandOutput::Help,
+    };
+
+    Ok(result)
+}
+
+/// Runs a command and prints its result. Asks for confirmation first when
+/// the command needs it, reading the answer from `stdin`, so
+/// `handle_command` itself never reads stdin. `writing` is set while a
+/// write runs, so `run_interruptible` lets it finish on Ctrl-C but can
+/// still cancel a question waiting for its answer.
+async fn execute(
+    db: &mut DatabaseClient,
+    stdin: &mut BufReader<tokio::io::Stdin>,
+    command: Command,
+    output: OutputFormat,
+    writing: &Cell<bool>,
+) -> Result<(), DatabaseError> {
+    if let Command::Clear { confirmed: false } = command {
+        if !confirm(stdin, "Delete every record in this database? Type 'yes' to confirm: ").await? {
+            info!("Clear cancelled");
+            return Ok(());
         }
     }
+
+    writing.set(command.is_write());
+    let result = handle_command(db, command, output).await?;
+    writing.set(false);
+    let offer_repair = output == OutputFormat::Text
+        && !db.is_read_only()
+        && matches!(&result, CommandOutput::Verify(report) if !report.is_consistent());
+    render(result, output)?;
+
+    if offer_repair && confirm(stdin, "Run repair to correct the metadata? Type 'yes' to confirm: ").await? {
+        writing.set(true);
+        let result = handle_command(db, Command::Repair, output).await?;
+        render(result, output)?;
+    }
+
     Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    log_with_timestamp("Starting Avail database application");
-
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() < 2 || args.len() > 3 {
-        log_with_timestamp("Error: Invalid number of arguments");
-        println!("Usage: cargo run -- <app_name> [block_range]");
-        println!("  app_name:       The human-readable app name");
-        println!("  block_range:    (Optional) How many blocks to look back when scanning");
-        return Ok(());
+/// A public key-value database on Avail, addressed by an app name
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// The human-readable app name
+    app_name: String,
+
+    /// Only log warnings and errors; overrides RUST_LOG
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Also log debug details such as each block fetched; overrides RUST_LOG
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Use this app ID instead of looking up the app name, which is then only
+    /// shown in logs; defaults to AVAIL_APP_ID
+    #[arg(long, global = true)]
+    app_id: Option<u32>,
+
+    /// How many blocks to look back when discovering an existing database
+    #[arg(long, global = true)]
+    block_range: Option<u32>,
+
+    /// How many blocks to fetch at once during scans [default: 8]
+    #[arg(long, global = true, value_parser = clap::value_parser!(u16).range(1..))]
+    concurrency: Option<u16>,
+
+    /// Avail network to use: turing (the default) or mainnet
+    #[arg(long, global = true)]
+    network: Option<avail::Network>,
+
+    /// Read settings from this TOML file; flags given as well override it
+    #[arg(long, global = true)]
+    config: Option<std::path::PathBuf>,
+
+    /// Sign writes with the named account, whose seed phrase is read from
+    /// AVAIL_SEED_<NAME>; defaults to AVAIL_SEED_PHRASE
+    #[arg(long, global = true)]
+    account: Option<String>,
+
+    /// Print command results as JSON
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Fail reads that find blobs which are not database entries instead of skipping them
+    #[arg(long, global = true)]
+    strict: bool,
+
+    /// Save the scanned state to this file and resume from it next time, e.g. .dadb_state.json
+    #[arg(long, global = true)]
+    state_file: Option<std::path::PathBuf>,
+
+    /// Remember the database's metadata in this file and skip discovery next time, e.g. .dadb_metadata.json
+    #[arg(long, global = true)]
+    metadata_file: Option<std::path::PathBuf>,
+
+    /// Log what each write would submit instead of sending it; nothing is written
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// Refuse every write, so the database can be inspected without changing it
+    #[arg(long, global = true)]
+    read_only: bool,
+
+    /// Don't check whether each add overwrites an existing key, which saves a read per add
+    #[arg(long, global = true)]
+    no_overwrite_warning: bool,
+
+    /// Run a single command and exit instead of starting the interactive prompt
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+}
+
+#[derive(Subcommand)]
+enum CliCommand {
+    /// Add a new record or update existing one
+    Add {
+        /// Tag the record; repeat for several tags
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+        key: String,
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        value: Vec<String>,
+    },
+    /// Retrieve a record by key
+    Get { key: String },
+    /// List all records, or one page of them
+    List {
+        offset: Option<usize>,
+        limit: Option<usize>,
+        /// Only records created after this RFC 3339 timestamp
+        #[arg(long, value_parser = parse_timestamp)]
+        created_after: Option<DateTime<Utc>>,
+        /// Only records created before this RFC 3339 timestamp
+        #[arg(long, value_parser = parse_timestamp)]
+        created_before: Option<DateTime<Utc>>,
+        /// Only records with this tag
+        #[arg(long)]
+        tag: Option<String>,
+        /// Print each record in full instead of as a table
+        #[arg(long)]
+        verbose: bool,
+    },
+    /// List the app names the account owns, with their IDs. The app name
+    /// argument is not looked up or created.
+    Apps,
+    /// Serve the database over a REST API instead of running a command
+    Serve {
+        /// Port to listen on
+        #[arg(long, default_value_t = 8080)]
+        port: u16,
+    },
+    /// Any other REPL command, e.g. `delete <key>`
+    #[command(external_subcommand)]
+    Other(Vec<String>),
+}
+
+impl CliCommand {
+    fn into_command(self) -> Result<Command, String> {
+        match self {
+            CliCommand::Add { tags, key, value } => Ok(Command::Add(key, value.join(" "), tags)),
+            CliCommand::Get { key } => Ok(Command::Get(key)),
+            CliCommand::List { offset, limit, created_after, created_before, tag, verbose } => {
+                Ok(Command::List(ListArgs {
+                    page: offset.map(|offset| (offset, limit.unwrap_or(DEFAULT_PAGE_SIZE))),
+                    filter: RecordFilter { created_after, created_before, tag },
+                    verbose,
+                }))
+            }
+            CliCommand::Serve { .. } => Err("serve starts the HTTP server and is not a database command".to_string()),
+            CliCommand::Apps => Err("apps lists app names and is not a database command".to_string()),
+            CliCommand::Other(args) => Command::from_words(&args),
+        }
+    }
+}
+
+/// Runs a REPL command, letting Ctrl-C cancel it and return to the prompt.
+/// A cancelled read is dropped at its next await point: the scan state is only
+/// updated once a scan completes, so nothing is left half-applied. Writes
+/// are allowed to complete, since dropping one could leave the record
+/// submitted but its metadata not; a write still waiting for confirmation
+/// has written nothing and is cancelled.
+async fn run_interruptible(
+    db: &mut DatabaseClient,
+    stdin: &mut BufReader<tokio::io::Stdin>,
+    cmd: Command,
+    output: OutputFormat,
+) {
+    let writing = Cell::new(false);
+    let mut command = pin!(execute(db, stdin, cmd, output, &writing));
+
+    loop {
+        tokio::select! {
+            result = &mut command => {
+                if let Err(e) = result {
+                    error!("{}", e);
+                }
+                return;
+            }
+            _ = tokio::signal::ctrl_c() => {
+                if writing.get() {
+                    warn!("Waiting for the write to complete before returning to the prompt");
+                } else {
+                    warn!("Cancelled");
+                    return;
+                }
+            }
+        }
     }
+}
 
-    let app_name = args[1].clone();
-    log_with_timestamp(&format!("Resolving app name: '{}'", app_name));
+/// Looks up the app ID registered under `app_name`, creating it if it
+/// doesn't exist and `can_create` allows the write
+async fn resolve_app_id(
+    conn: &avail::AvailConnection,
+    app_name: &str,
+    can_create: bool,
+) -> Result<u32, Box<dyn std::error::Error>> {
+    info!("Resolving app name: '{}'", app_name);
 
     // Try to fetch app_id by name
-    let app_id = match avail::does_app_id_exist_on_avail(&app_name).await {
+    match avail::does_app_id_exist_on_avail(conn, app_name).await {
         Ok(Some(id)) => {
-            log_with_timestamp(&format!("Found existing app ID: {}", id));
-            id
+            info!("Found existing app ID: {}", id);
+            Ok(id)
+        }
+        Ok(None) if !can_create => {
+            let msg = format!("App '{}' does not exist, and it can't be created without writing", app_name);
+            error!("{}", msg);
+            Err(Box::<dyn std::error::Error>::from(msg))
         }
         Ok(None) => {
-            log_with_timestamp("App not found. Creating new app ID...");
+            info!("App not found. Creating new app ID...");
     
-            avail::create_app_id_on_avail(&app_name).await.map_err(|e| {
-                let msg = format!("Error creating app ID: {:?}", e);
-                log_with_timestamp(&msg);
+            // The new ID is taken from the creation event: with finalized
+            // reads, a lookup right after inclusion would not see it yet
+            let id = avail::create_app_id_on_avail(conn, app_name).await.map_err(|e| {
+                let msg = format!("Error creating app ID: {}", e);
+                error!("{}", msg);
                 Box::<dyn std::error::Error>::from(msg)
             })?;
-    
-            // Fetch again after creation
-            avail::does_app_id_exist_on_avail(&app_name)
-                .await
-                .map_err(|e| {
-                    let msg = format!("Error fetching app ID after creation: {:?}", e);
-                    log_with_timestamp(&msg);
-                    Box::<dyn std::error::Error>::from(msg)
-                })?
-                .ok_or_else(|| {
-                    let msg = "App ID should exist after creation, but wasn't found.".to_string();
-                    log_with_timestamp(&msg);
-                    Box::<dyn std::error::Error>::from(msg)
-                })?
+            info!("Created app ID: {}", id);
+            Ok(id)
         }
         Err(e) => {
             let msg = format!("Error checking app ID: {:?}", e);
-            log_with_timestamp(&msg);
-            return Err(Box::<dyn std::error::Error>::from(msg));
+            error!("{}", msg);
+            Err(Box::<dyn std::error::Error>::from(msg))
         }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+
+    // JSON output is usually read by scripts, which don't need timestamps
+    // on the log lines next to it
+    let verbosity = match (cli.quiet, cli.verbose) {
+        (true, _) => Verbosity::Quiet,
+        (_, true) => Verbosity::Verbose,
+        _ => Verbosity::Normal,
     };
-    
+    init_logging(!cli.json, verbosity);
+    info!("Starting Avail database application");
 
-    let block_range = if args.len() == 3 {
-        Some(args[2].parse::<u32>().map_err(|_| {
-            let msg = "block_range must be a valid number".to_string();
-            log_with_timestamp(&msg);
-            std::io::Error::new(std::io::ErrorKind::InvalidInput, msg)
-        })?)
+    let output = if cli.json {
+        OutputFormat::Json
     } else {
-        None
+        OutputFormat::Text
+    };
+    // Flags override the config file, which overrides the defaults. A
+    // boolean flag can only switch its setting on, not off. Unlike the
+    // library, the CLI warns about overwrites unless told not to.
+    let defaults = DatabaseClientConfig::new().warn_on_overwrite(true);
+    let mut config = match &cli.config {
+        Some(path) => defaults.with_file(path).inspect_err(|e| error!("{}", e))?,
+        None => defaults,
+    };
+    if let Some(network) = cli.network {
+        config = config.network(network);
+    }
+    if let Some(block_range) = cli.block_range {
+        config = config.block_range(block_range);
+    }
+    if let Some(concurrency) = cli.concurrency {
+        config = config.concurrency(concurrency as usize);
+    }
+    if let Some(state_file) = cli.state_file {
+        config = config.state_file(state_file);
+    }
+    if let Some(metadata_file) = cli.metadata_file {
+        config = config.metadata_file(metadata_file);
+    }
+    if let Some(account) = cli.account {
+        config = config.account(account);
+    }
+    config.strict |= cli.strict;
+    config.dry_run |= cli.dry_run;
+    config.read_only |= cli.read_only;
+    if cli.no_overwrite_warning {
+        config = config.warn_on_overwrite(false);
+    }
+
+    let network = config.network;
+    let app_name = cli.app_name;
+    let dry_run = config.dry_run;
+    let read_only = config.read_only;
+    let app_id = match cli.app_id {
+        Some(app_id) => Some(app_id),
+        None => app_id_from_env().inspect_err(|e| error!("{}", e))?,
+    };
+
+    // Parse the command up front so a typo fails before connecting
+    let list_apps = matches!(cli.command, Some(CliCommand::Apps));
+    let (command, serve_port) = match cli.command {
+        Some(CliCommand::Serve { port }) => (None, Some(port)),
+        Some(CliCommand::Apps) => (None, None),
+        command => {
+            let command = command
+                .map(CliCommand::into_command)
+                .transpose()
+                .map_err(|msg| {
+                    error!("Command error: {}", msg);
+                    std::io::Error::new(std::io::ErrorKind::InvalidInput, msg)
+                })?;
+            (command, None)
+        }
+    };
+
+    info!("Selected network: {}", network);
+
+    let endpoints = avail::AvailEndpoints::from_env(network).map_err(|e| {
+        let msg = format!("Error reading endpoint configuration: {:?}", e);
+        error!("{}", msg);
+        Box::<dyn std::error::Error>::from(msg)
+    })?;
+
+    info!("Connecting to Avail node at {}...", endpoints.ws_url);
+    let mut conn = avail::AvailConnection::connect(network, endpoints, config.account.as_deref(), config.needs_account())
+        .await
+        .map_err(|e| {
+            let msg = format!("Error connecting to Avail node: {:?}", e);
+            error!("{}", msg);
+            Box::<dyn std::error::Error>::from(msg)
+        })?;
+    config.configure(&mut conn);
+    // Scripts reading --json output don't want a bar, and --quiet asks for none
+    conn.show_progress = output == OutputFormat::Text && verbosity != Verbosity::Quiet && io::stderr().is_terminal();
+    if dry_run {
+        warn!("Dry run: writes are logged, not submitted");
+    }
+    if read_only {
+        warn!("Read-only: writes are refused");
+    }
+
+    if list_apps {
+        let apps = avail::list_owned_app_ids_on_avail(&conn).await.map_err(|e| {
+            let msg = format!("Error listing app IDs: {:?}", e);
+            error!("{}", msg);
+            Box::<dyn std::error::Error>::from(msg)
+        })?;
+
+        if output == OutputFormat::Json {
+            print_json(&apps)?;
+        } else if apps.is_empty() {
+            println!("This account owns no app IDs");
+        } else {
+            for app in apps {
+                println!("{} -> {}", app.name, app.id);
+            }
+        }
+        return Ok(());
+    }
+
+    let app_id = match app_id {
+        Some(id) => {
+            info!("Using app ID {} without resolving the app name", id);
+            id
+        }
+        None => resolve_app_id(&conn, &app_name, !(dry_run || read_only)).await?,
     };
+    let config = config.app_id(app_id);
 
-    let block_limit = block_range.unwrap_or(10);
-    log_with_timestamp(&format!("Block search limit: {} blocks", block_limit));
-    log_with_timestamp(&format!("Configuration - App name: '{}', App ID: {}", app_name, app_id));
-    log_with_timestamp("Connecting to Avail node...");
+    info!("Block search limit: {} blocks", config.block_range);
+    info!(
+        "Configuration - Network: {}, App name: '{}', App ID: {}",
+        conn.network, app_name, app_id
+    );
 
-    let mut db = DatabaseClient::new(app_id, block_range).await.map_err(|e| {
+    let backend = Box::new(AvailRpcBackend::new(conn));
+    let mut db = DatabaseClient::with_backend(backend, config).await.map_err(|e| {
         let msg = format!("Error initializing database client: {:?}", e);
-        log_with_timestamp(&msg);
+        error!("{}", msg);
         std::io::Error::new(std::io::ErrorKind::Other, msg)
     })?;
 
-    log_with_timestamp("Successfully connected to Avail node");
-    log_with_timestamp("Database client initialized");
+    info!("Successfully connected to Avail node");
+    info!("Database client initialized");
+
+    if let Some(port) = serve_port {
+        return Ok(server::serve(db, port).await?);
+    }
+
+    let mut stdin = BufReader::new(tokio::io::stdin());
+
+    if let Some(command) = command {
+        return execute(&mut db, &mut stdin, command, output, &Cell::new(false)).await.map_err(|e| {
+            error!("{}", e);
+            Box::<dyn std::error::Error>::from(e.to_string())
+        });
+    }
+
+    if output == OutputFormat::Text {
+        print_help();
+        println!("\nEnter commands below:");
+    }
 
-    println!("\nAvailable commands:");
-    println!("  add <key> <value>  - Add a new record or update existing one");
-    println!("  get <key>          - Retrieve a record by key");
-    println!("  list               - List all records");
-    println!("  exit               - Exit the application");
-    println!("  help               - Show this help message");
-    println!("\nEnter commands below:");
-
-    let stdin = io::stdin();
     let mut input = String::new();
 
     loop {
-        print!("> ");
-        io::stdout().flush()?;
+        if output == OutputFormat::Text {
+            let prompt = match (dry_run, read_only) {
+                (_, true) => "[read-only] > ",
+                (true, false) => "[dry run] > ",
+                (false, false) => "> ",
+            };
+            print!("{}", prompt);
+            io::stdout().flush()?;
+        }
         input.clear();
-        // Fix: StdinLock does not have read_line, use stdin.read_line instead
-        stdin.read_line(&mut input)?;
-    
+        // Ctrl-C at the prompt exits, like end of input
+        let read = tokio::select! {
+            read = stdin.read_line(&mut input) => read?,
+            _ = tokio::signal::ctrl_c() => 0,
+        };
+        if read == 0 {
+            return Ok(());
+        }
+
         match Command::from_str(&input) {
-            Ok(cmd) => {
-                if let Err(e) = handle_command(&mut db, cmd).await {
-                    log_with_timestamp(&format!("Error: {}", e));
-                }
-            }
+            Ok(cmd) => run_interruptible(&mut db, &mut stdin, cmd, output).await,
             Err(e) => {
-                log_with_timestamp(&format!("Command error: {}", e));
+                error!("Command error: {}", e);
             }
         }
     }
     
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add_value(line: &str) -> String {
+        match line.parse::<Command>() {
+            Ok(Command::Add(_, value, _)) => value,
+            Ok(_) => panic!("'{}' did not parse as add", line),
+            Err(e) => panic!("'{}' did not parse: {}", line, e),
+        }
+    }
+
+    #[test]
+    fn quoted_word_is_stored_verbatim() {
+        assert_eq!(add_value(r#"add key "a  b""#), "a  b");
+        assert_eq!(add_value(r#"add key "say \"hi\"""#), r#"say "hi""#);
+    }
+
+    #[test]
+    fn quotes_inside_a_json_value_are_kept() {
+        assert_eq!(add_value(r#"add k {"a": "b" }"#), r#"{"a": "b" }"#);
+        assert_eq!(add_value(r#"add k ["x", "y" ]"#), r#"["x", "y" ]"#);
+        assert_eq!(add_value(r#"add k {"name": "Naruto"}"#), r#"{"name": "Naruto"}"#);
+    }
+
+    #[test]
+    fn unbalanced_quote_is_an_error() {
+        assert!(tokenize(r#"add key "a b"#).is_err());
+    }
+}
@@ -1,8 +1,14 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use thiserror::Error;
+use tracing::warn;
 use uuid::Uuid;
 
+use crate::avail::BlobLocation;
+use crate::bloom::BloomFilter;
+
 /// Errors that can occur during database operations
 #[derive(Debug, Error)]
 pub enum DatabaseError {
@@ -10,7 +16,37 @@ pub enum DatabaseError {
     AvailError(String),
 
     #[error("Serialization error: {0}")]
-    SerializationError(String)
+    SerializationError(String),
+
+    #[error("Record not found: {0}")]
+    NotFound(String),
+
+    #[error("Invalid key: {0}")]
+    InvalidKey(String),
+
+    #[error("IO error: {0}")]
+    IoError(String),
+
+    #[error("Invalid input: {0}")]
+    InvalidInput(String),
+
+    #[error("Decryption failed: {0}")]
+    DecryptionError(String),
+
+    #[error("Blob too large: {size} bytes exceeds the maximum of {max} bytes")]
+    BlobTooLarge { size: usize, max: usize },
+
+    #[error("Record '{key}' is incomplete: found {found} of {total} chunks")]
+    MissingChunks { key: String, found: usize, total: u32 },
+
+    #[error("Found {0} blobs that are neither metadata nor records")]
+    UnrecognizedBlobs(usize),
+
+    #[error("Refusing to write: the database is open read-only")]
+    ReadOnly,
+
+    #[error("Version conflict on '{key}': expected version {expected}, found {found}")]
+    Conflict { key: String, expected: u64, found: u64 },
 }
 
 /// Represents a record in the database
@@ -21,6 +57,58 @@ pub struct Record {
     pub created_at: DateTime<Utc>,
     pub updated_at: Option<DateTime<Utc>>,
     pub id: String,
+    /// Marks this record as a tombstone: the key is considered deleted
+    #[serde(default)]
+    pub deleted: bool,
+    /// How `value` is encoded; `None` for plain text, `Some("base64")` for binary values
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+    /// Position of this blob's part of `value` when the record is split
+    /// across several blobs sharing its `id`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_index: Option<u32>,
+    /// Number of chunks the record was split into
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_total: Option<u32>,
+    /// Labels for grouping records, see `DatabaseClient::list_records_by_tag`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Incremented by every update of the record, starting at 1; 0 for
+    /// records written before versions existed. See
+    /// `DatabaseClient::compare_and_swap`.
+    #[serde(default)]
+    pub version: u64,
+}
+
+/// `Record::encoding` marker for binary values stored as base64
+pub const BASE64_ENCODING: &str = "base64";
+
+/// Longest key in bytes `validate_key` accepts. Every blob repeats its key,
+/// and keys are meant to be typed and listed, so they are kept short.
+pub const MAX_KEY_LENGTH: usize = 256;
+
+/// Rejects keys that would be hard or impossible to look up again: empty
+/// keys, keys over `MAX_KEY_LENGTH` bytes and keys containing control
+/// characters (including newlines and tabs)
+pub fn validate_key(key: &str) -> Result<(), DatabaseError> {
+    if key.is_empty() {
+        return Err(DatabaseError::InvalidKey("key must not be empty".to_string()));
+    }
+    if key.len() > MAX_KEY_LENGTH {
+        return Err(DatabaseError::InvalidKey(format!(
+            "key is {} bytes, the maximum is {}",
+            key.len(),
+            MAX_KEY_LENGTH
+        )));
+    }
+    if key.chars().any(char::is_control) {
+        return Err(DatabaseError::InvalidKey(format!(
+            "key {:?} contains control characters",
+            key
+        )));
+    }
+
+    Ok(())
 }
 
 impl Record {
@@ -31,16 +119,155 @@ impl Record {
             created_at: Utc::now(),
             updated_at: None,
             id: Uuid::new_v4().to_string(),
+            deleted: false,
+            encoding: None,
+            chunk_index: None,
+            chunk_total: None,
+            tags: Vec::new(),
+            version: 1,
+        }
+    }
+
+    /// Sets the record's tags
+    pub fn with_tags(mut self, tags: Vec<String>) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    /// Creates a record holding arbitrary bytes, stored base64-encoded in `value`
+    pub fn new_binary(key: String, bytes: Vec<u8>) -> Self {
+        Self {
+            encoding: Some(BASE64_ENCODING.to_string()),
+            ..Self::new(key, BASE64.encode(bytes))
+        }
+    }
+
+    /// Decodes the bytes of a binary record. Returns `None` for text records
+    /// or if the stored value is not valid base64.
+    pub fn value_bytes(&self) -> Option<Vec<u8>> {
+        if self.encoding.as_deref() != Some(BASE64_ENCODING) {
+            return None;
+        }
+
+        BASE64.decode(&self.value).ok()
+    }
+
+    /// Whether this record is one chunk of a value split across several blobs
+    pub fn is_chunk(&self) -> bool {
+        self.chunk_total.is_some()
+    }
+
+    /// Creates a tombstone record marking the key as deleted
+    pub fn tombstone(key: String) -> Self {
+        Self {
+            key,
+            value: String::new(),
+            created_at: Utc::now(),
+            updated_at: None,
+            id: Uuid::new_v4().to_string(),
+            deleted: true,
+            encoding: None,
+            chunk_index: None,
+            chunk_total: None,
+            tags: Vec::new(),
+            version: 0,
         }
     }
 }
 
+/// A page of records returned by a paginated listing
+#[derive(Debug, Clone)]
+pub struct RecordPage {
+    pub records: Vec<Record>,
+    /// Total number of records across all pages
+    pub total: usize,
+}
+
+/// An overview of a database's size and health, from `DatabaseClient::stats`
+#[derive(Debug, Clone, Serialize)]
+pub struct DbStats {
+    pub app_id: u32,
+    pub start_height: u64,
+    /// Latest block height reads scan up to
+    pub latest_height: u32,
+    /// Blocks from `start_height` to `latest_height`, inclusive
+    pub blocks_scanned: u64,
+    /// Distinct live keys
+    pub record_count: usize,
+    /// Every entry found under the app ID: records, tombstones, chunks,
+    /// metadata, snapshots and unrecognized blobs
+    pub total_blobs: usize,
+    /// Entries that parse as no known blob type
+    pub unrecognized_blobs: usize,
+    /// Record entries that repeat an earlier copy of the same write, left
+    /// behind by a submission retried after it had already landed. Reads
+    /// count each write once, so they are harmless.
+    pub duplicate_entries: usize,
+    /// Free balance in Planck of the account paying for writes; `None` when
+    /// the backend has no such account
+    pub balance: Option<u128>,
+}
+
+/// How the metadata's `record_count` compares with the data, from
+/// `DatabaseClient::verify_metadata`
+#[derive(Debug, Clone, Serialize)]
+pub struct MetadataReport {
+    /// `record_count` according to the current metadata
+    pub metadata_count: u64,
+    /// Distinct live keys found by scanning the data
+    pub live_keys: usize,
+    /// Record writes found, tombstones excluded. A chunked write or one
+    /// submitted twice counts once.
+    pub record_writes: usize,
+    /// Deletions found, one tombstone per key deleted
+    pub tombstones: usize,
+}
+
+impl MetadataReport {
+    /// How many more records the metadata counts than there are live keys;
+    /// negative when it counts fewer
+    pub fn delta(&self) -> i64 {
+        self.metadata_count as i64 - self.live_keys as i64
+    }
+
+    pub fn is_consistent(&self) -> bool {
+        self.delta() == 0
+    }
+}
+
+/// Evidence that a record was read from a blob included on-chain: where the
+/// blob is and the exact entry the record was parsed from. See
+/// `DatabaseClient::verify_inclusion` for what checking it guarantees.
+#[derive(Debug, Clone)]
+pub struct InclusionProof {
+    pub location: BlobLocation,
+    /// The serialized entry within the blob, as submitted
+    pub entry: String,
+}
+
 /// Metadata for the database, stored in the first blob
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DatabaseMetadata {
     pub record_count: u64,
     pub last_updated: DateTime<Utc>,
     pub start_height: u64,
+    /// Block height the latest write to the database was included in
+    #[serde(default)]
+    pub checkpoint_height: u64,
+    /// Block height the latest snapshot blob was included in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot_height: Option<u64>,
+    /// Filter over every key written up to some height, letting lookups of
+    /// missing keys skip most of the scan
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_filter: Option<KeyFilter>,
+}
+
+/// A Bloom filter over every key with an entry in the blocks up to `height`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KeyFilter {
+    pub height: u64,
+    pub filter: BloomFilter,
 }
 
 impl Default for DatabaseMetadata {
@@ -49,6 +276,86 @@ impl Default for DatabaseMetadata {
             record_count: 0,
             last_updated: Utc::now(),
             start_height: 1,
+            checkpoint_height: 1,
+            snapshot_height: None,
+            key_filter: None,
         }
     }
 }
+
+
+/// The current state of the database as of block `height`, written as one
+/// blob so reads can start from it instead of scanning the whole history
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Snapshot {
+    /// Every block up to and including this height is reflected in `records`
+    pub height: u64,
+    pub created_at: DateTime<Utc>,
+    /// The newest version of every live key, as stored on-chain (values stay
+    /// encrypted), plus the chunks of records not yet complete at `height`
+    pub records: Vec<Record>,
+    /// Where the newest entry of each key was originally included
+    #[serde(default)]
+    pub locations: HashMap<String, BlobLocation>,
+}
+
+/// Version of the blob format this client writes and understands. Bump it
+/// when a change would make older clients misread new entries.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The `schema_version` stamped on every entry, read before the rest.
+/// Entries written before versioning have none and count as version 0.
+#[derive(Deserialize)]
+struct Envelope {
+    #[serde(default)]
+    schema_version: u32,
+}
+
+/// An entry stored in an Avail blob, tagged with its type so scans never
+/// have to guess what they are looking at
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Blob {
+    Metadata(DatabaseMetadata),
+    Record(Record),
+    Snapshot(Snapshot),
+}
+
+impl Blob {
+    /// Parses a blob entry. Entries written before the `type` tag existed
+    /// are still recognized by trying each untagged shape in turn. Entries
+    /// from a newer `schema_version` than `SCHEMA_VERSION` are skipped with a
+    /// warning, since this client can't tell what they mean.
+    pub fn parse(entry: &str) -> Option<Self> {
+        if let Ok(envelope) = serde_json::from_str::<Envelope>(entry) {
+            if envelope.schema_version > SCHEMA_VERSION {
+                warn!(
+                    "Skipping entry with schema version {}, newer than the supported {}: upgrade this client to read it",
+                    envelope.schema_version, SCHEMA_VERSION
+                );
+                return None;
+            }
+        }
+        if let Ok(blob) = serde_json::from_str::<Blob>(entry) {
+            return Some(blob);
+        }
+        if let Ok(metadata) = serde_json::from_str::<DatabaseMetadata>(entry) {
+            return Some(Blob::Metadata(metadata));
+        }
+        if let Ok(record) = serde_json::from_str::<Record>(entry) {
+            return Some(Blob::Record(record));
+        }
+
+        None
+    }
+
+    /// Serializes the tagged entry for submission, stamped with `SCHEMA_VERSION`
+    pub fn to_json(&self) -> Result<String, DatabaseError> {
+        let mut value = serde_json::to_value(self).map_err(|e| DatabaseError::SerializationError(e.to_string()))?;
+        if let Some(object) = value.as_object_mut() {
+            object.insert("schema_version".to_string(), SCHEMA_VERSION.into());
+        }
+
+        serde_json::to_string(&value).map_err(|e| DatabaseError::SerializationError(e.to_string()))
+    }
+}
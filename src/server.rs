@@ -0,0 +1,112 @@
+use axum::extract::rejection::{JsonRejection, QueryRejection};
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use da_db_implementation::{DatabaseClient, DatabaseError, Record, RecordFilter};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::{error, info};
+
+use crate::receipt_json;
+
+/// Writes need `&mut DatabaseClient`, so requests take turns on one client
+type SharedDb = Arc<Mutex<DatabaseClient>>;
+
+/// Body of `POST /records`
+#[derive(Deserialize)]
+struct NewRecord {
+    key: String,
+    value: String,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+/// A `DatabaseError` rendered as `{"error": "..."}` with a matching status code
+struct ApiError(DatabaseError);
+
+impl From<DatabaseError> for ApiError {
+    fn from(e: DatabaseError) -> Self {
+        Self(e)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = match &self.0 {
+            DatabaseError::NotFound(_) => StatusCode::NOT_FOUND,
+            DatabaseError::InvalidKey(_)
+            | DatabaseError::InvalidInput(_)
+            | DatabaseError::BlobTooLarge { .. } => StatusCode::BAD_REQUEST,
+            DatabaseError::ReadOnly => StatusCode::FORBIDDEN,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        if status == StatusCode::INTERNAL_SERVER_ERROR {
+            error!("{}", self.0);
+        }
+
+        (status, Json(json!({ "error": self.0.to_string() }))).into_response()
+    }
+}
+
+async fn get_record(
+    State(db): State<SharedDb>,
+    Path(key): Path<String>,
+) -> Result<Json<Record>, ApiError> {
+    let db = db.lock().await;
+
+    match db.get_record(&key).await? {
+        Some(record) => Ok(Json(record)),
+        None => Err(DatabaseError::NotFound(key).into()),
+    }
+}
+
+async fn list_records(
+    State(db): State<SharedDb>,
+    filter: Result<Query<RecordFilter>, QueryRejection>,
+) -> Result<Json<Vec<Record>>, ApiError> {
+    let Query(filter) = filter.map_err(|e| DatabaseError::InvalidInput(e.body_text()))?;
+
+    let db = db.lock().await;
+
+    Ok(Json(db.list_records_matching(&filter).await?))
+}
+
+async fn add_record(
+    State(db): State<SharedDb>,
+    body: Result<Json<NewRecord>, JsonRejection>,
+) -> Result<impl IntoResponse, ApiError> {
+    let Json(new_record) = body.map_err(|e| DatabaseError::InvalidInput(e.body_text()))?;
+
+    let mut db = db.lock().await;
+    let receipt = db.add_record(Record::new(new_record.key, new_record.value).with_tags(new_record.tags)).await?;
+
+    Ok((StatusCode::CREATED, Json(receipt_json(&receipt))))
+}
+
+async fn delete_record(
+    State(db): State<SharedDb>,
+    Path(key): Path<String>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let mut db = db.lock().await;
+
+    let receipt = db.delete_record(&key).await?;
+
+    Ok(Json(receipt_json(&receipt)))
+}
+
+/// Serves the database over HTTP on `0.0.0.0:<port>` until the process exits
+pub async fn serve(db: DatabaseClient, port: u16) -> std::io::Result<()> {
+    let app = Router::new()
+        .route("/records", get(list_records).post(add_record))
+        .route("/records/:key", get(get_record).delete(delete_record))
+        .with_state(Arc::new(Mutex::new(db)));
+
+    let listener = tokio::net::TcpListener::bind(("0.0.0.0", port)).await?;
+    info!("Serving the database on http://{}", listener.local_addr()?);
+
+    axum::serve(listener, app).await
+}